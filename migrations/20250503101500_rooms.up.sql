@@ -0,0 +1,14 @@
+CREATE TABLE rooms(
+	id UUID DEFAULT gen_random_uuid() PRIMARY KEY,
+	name TEXT NOT NULL,
+	entity_id TEXT NOT NULL UNIQUE,
+	icon_url TEXT NOT NULL,
+	sort_order INTEGER NOT NULL DEFAULT 0,
+	enabled boolean NOT NULL DEFAULT true
+);
+
+INSERT INTO rooms (name, entity_id, icon_url, sort_order) VALUES
+	('Playroom', 'media_player.playroom', '/public/icons/rooms/Playroom.png', 0),
+	('Bathroom', 'media_player.bathroom', '/public/icons/rooms/Bathroom.png', 1),
+	('Kitchen', 'media_player.kitchen', '/public/icons/rooms/Kitchen.png', 2),
+	('Living Room', 'media_player.living_room', '/public/icons/rooms/LivingRoom.png', 3);
@@ -0,0 +1,8 @@
+CREATE TABLE playback_positions(
+	entry_id UUID NOT NULL REFERENCES entries(id) ON DELETE CASCADE,
+	room_id UUID NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+	track_number INTEGER NOT NULL,
+	position DOUBLE PRECISION NOT NULL,
+	updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+	PRIMARY KEY (entry_id, room_id)
+);
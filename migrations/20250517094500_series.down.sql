@@ -0,0 +1,11 @@
+ALTER TABLE categories 
+DROP COLUMN last_room_id;
+
+ALTER TABLE categories 
+DROP COLUMN last_entry_id;
+
+ALTER TABLE entries 
+DROP COLUMN episode_number;
+
+ALTER TABLE categories 
+DROP COLUMN series;
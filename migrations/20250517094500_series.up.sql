@@ -0,0 +1,10 @@
+ALTER TABLE categories ADD COLUMN series boolean NOT NULL DEFAULT false;
+ALTER TABLE entries ADD COLUMN episode_number INTEGER;
+ALTER TABLE categories ADD COLUMN last_entry_id UUID REFERENCES entries(id) ON DELETE SET NULL;
+ALTER TABLE categories ADD COLUMN last_room_id UUID REFERENCES rooms(id) ON DELETE SET NULL;
+
+UPDATE entries
+SET episode_number = COALESCE(
+	substring(name from '(?i)(?:folge|episode|teil)\s*(\d+)'),
+	substring(name from '^\s*(\d+)')
+)::int;
@@ -0,0 +1,5 @@
+ALTER TABLE rooms 
+DROP COLUMN max_volume;
+
+ALTER TABLE rooms 
+DROP COLUMN default_volume;
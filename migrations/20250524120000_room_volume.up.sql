@@ -0,0 +1,4 @@
+ALTER TABLE rooms ADD COLUMN default_volume REAL NOT NULL DEFAULT 0.3;
+ALTER TABLE rooms ADD COLUMN max_volume REAL NOT NULL DEFAULT 0.6;
+ALTER TABLE rooms ADD CONSTRAINT rooms_volume_check
+	CHECK (0 <= default_volume AND default_volume <= max_volume AND max_volume <= 1);
@@ -0,0 +1,5 @@
+CREATE TABLE sleep_timers(
+	room_id UUID PRIMARY KEY REFERENCES rooms(id) ON DELETE CASCADE,
+	fires_at TIMESTAMPTZ NOT NULL,
+	fired_at TIMESTAMPTZ
+);
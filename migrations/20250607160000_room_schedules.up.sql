@@ -0,0 +1,6 @@
+CREATE TABLE room_schedules(
+	id UUID DEFAULT gen_random_uuid() PRIMARY KEY,
+	room_id UUID NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+	starts_at TIME NOT NULL,
+	ends_at TIME NOT NULL
+);
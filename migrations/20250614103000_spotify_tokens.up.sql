@@ -0,0 +1,5 @@
+CREATE TABLE spotify_tokens(
+	id BOOLEAN DEFAULT TRUE PRIMARY KEY CHECK (id),
+	refresh_token TEXT NOT NULL,
+	updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
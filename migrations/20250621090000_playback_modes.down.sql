@@ -0,0 +1,9 @@
+ALTER TABLE entries
+	DROP COLUMN shuffle,
+	DROP COLUMN repeat;
+
+ALTER TABLE categories
+	DROP COLUMN shuffle,
+	DROP COLUMN repeat;
+
+DROP TYPE repeat_mode;
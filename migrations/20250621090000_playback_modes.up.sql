@@ -0,0 +1,12 @@
+CREATE TYPE repeat_mode AS ENUM ('off', 'all', 'one');
+
+ALTER TABLE categories
+	ADD COLUMN shuffle BOOLEAN NOT NULL DEFAULT FALSE,
+	ADD COLUMN repeat repeat_mode NOT NULL DEFAULT 'off';
+
+UPDATE categories SET shuffle = TRUE WHERE category_type = 'music';
+
+-- NULL falls back to the category
+ALTER TABLE entries
+	ADD COLUMN shuffle BOOLEAN,
+	ADD COLUMN repeat repeat_mode;
@@ -0,0 +1,3 @@
+DROP TABLE room_plays;
+
+ALTER TABLE rooms DROP COLUMN allow_takeover;
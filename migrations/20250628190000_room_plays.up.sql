@@ -0,0 +1,7 @@
+ALTER TABLE rooms ADD COLUMN allow_takeover BOOLEAN NOT NULL DEFAULT TRUE;
+
+CREATE TABLE room_plays(
+	room_id UUID PRIMARY KEY REFERENCES rooms(id) ON DELETE CASCADE,
+	entry_id UUID NOT NULL REFERENCES entries(id) ON DELETE CASCADE,
+	started_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,3 @@
+ALTER TABLE entries DROP COLUMN spoken_name;
+
+ALTER TABLE rooms DROP COLUMN announce;
@@ -0,0 +1,3 @@
+ALTER TABLE rooms ADD COLUMN announce BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE entries ADD COLUMN spoken_name TEXT;
@@ -0,0 +1,5 @@
+DROP TABLE track_artists;
+
+DROP TABLE tracks;
+
+DROP TABLE artists;
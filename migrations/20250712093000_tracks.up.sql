@@ -0,0 +1,53 @@
+CREATE TABLE artists(
+	id UUID DEFAULT gen_random_uuid() PRIMARY KEY,
+	spotify_id TEXT NOT NULL UNIQUE,
+	name TEXT NOT NULL
+);
+
+CREATE TABLE tracks(
+	id UUID DEFAULT gen_random_uuid() PRIMARY KEY,
+	entry_id UUID NOT NULL REFERENCES entries(id) ON DELETE CASCADE,
+	track_number INT NOT NULL,
+	spotify_id TEXT NOT NULL,
+	name TEXT NOT NULL,
+	duration_ms INT NOT NULL,
+	UNIQUE (entry_id, track_number)
+);
+
+CREATE INDEX tracks_spotify_id ON tracks(spotify_id);
+
+CREATE TABLE track_artists(
+	track_id UUID NOT NULL REFERENCES tracks(id) ON DELETE CASCADE,
+	artist_id UUID NOT NULL REFERENCES artists(id) ON DELETE CASCADE,
+	position INT NOT NULL,
+	PRIMARY KEY (track_id, position)
+);
+
+-- album blobs list the tracks directly, playlist blobs wrap them in an item with added_at
+CREATE TEMPORARY TABLE blob_tracks AS
+SELECT e.id AS entry_id, t.number::int AS track_number, COALESCE(t.item->'track', t.item) AS track
+FROM entries AS e,
+	json_array_elements(e.blob->'tracks'->'items') WITH ORDINALITY AS t(item, number)
+WHERE json_typeof(e.blob->'tracks'->'items') = 'array';
+
+DELETE FROM blob_tracks WHERE json_typeof(track) <> 'object' OR track->>'id' IS NULL;
+
+INSERT INTO artists (spotify_id, name)
+SELECT DISTINCT ON (a.artist->>'id') a.artist->>'id', COALESCE(a.artist->>'name', '')
+FROM blob_tracks AS b,
+	json_array_elements(b.track->'artists') AS a(artist)
+WHERE json_typeof(b.track->'artists') = 'array' AND a.artist->>'id' IS NOT NULL;
+
+INSERT INTO tracks (entry_id, track_number, spotify_id, name, duration_ms)
+SELECT entry_id, track_number, track->>'id', COALESCE(track->>'name', ''), COALESCE((track->>'duration_ms')::int, 0)
+FROM blob_tracks;
+
+INSERT INTO track_artists (track_id, artist_id, position)
+SELECT t.id, ar.id, a.position::int
+FROM blob_tracks AS b
+JOIN tracks AS t ON t.entry_id = b.entry_id AND t.track_number = b.track_number,
+	json_array_elements(b.track->'artists') WITH ORDINALITY AS a(artist, position)
+JOIN artists AS ar ON ar.spotify_id = a.artist->>'id'
+WHERE json_typeof(b.track->'artists') = 'array';
+
+DROP TABLE blob_tracks;
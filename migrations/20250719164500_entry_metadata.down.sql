@@ -0,0 +1,8 @@
+ALTER TABLE entries
+	DROP COLUMN explicit,
+	DROP COLUMN primary_artist,
+	DROP COLUMN track_count,
+	DROP COLUMN duration_ms,
+	DROP COLUMN release_date;
+
+ALTER TABLE tracks DROP COLUMN explicit;
@@ -0,0 +1,51 @@
+ALTER TABLE tracks ADD COLUMN explicit BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE entries
+	ADD COLUMN release_date DATE,
+	ADD COLUMN duration_ms BIGINT NOT NULL DEFAULT 0,
+	ADD COLUMN track_count INT NOT NULL DEFAULT 0,
+	ADD COLUMN primary_artist TEXT,
+	ADD COLUMN explicit BOOLEAN NOT NULL DEFAULT FALSE;
+
+UPDATE tracks AS t
+SET explicit = TRUE
+FROM entries AS e,
+	json_array_elements(e.blob->'tracks'->'items') WITH ORDINALITY AS i(item, number)
+WHERE t.entry_id = e.id
+	AND t.track_number = i.number
+	AND json_typeof(e.blob->'tracks'->'items') = 'array'
+	AND json_typeof(COALESCE(i.item->'track', i.item)) = 'object'
+	AND COALESCE(i.item->'track', i.item)->>'explicit' = 'true';
+
+-- spotify reports the release date with year, month or day precision
+UPDATE entries AS e
+SET
+	track_count = s.track_count,
+	duration_ms = s.duration_ms,
+	explicit = s.explicit,
+	release_date = CASE
+		WHEN e.blob->>'release_date' ~ '^\d{4}-\d{2}-\d{2}$' THEN to_date(e.blob->>'release_date', 'YYYY-MM-DD')
+		WHEN e.blob->>'release_date' ~ '^\d{4}-\d{2}$' THEN to_date(e.blob->>'release_date', 'YYYY-MM')
+		WHEN e.blob->>'release_date' ~ '^\d{4}$' THEN to_date(e.blob->>'release_date', 'YYYY')
+	END,
+	primary_artist = COALESCE(e.blob->'artists'->0->>'name', s.primary_artist)
+FROM (
+	SELECT
+		en.id AS entry_id,
+		COUNT(t.id)::int AS track_count,
+		COALESCE(SUM(t.duration_ms), 0)::bigint AS duration_ms,
+		COALESCE(BOOL_OR(t.explicit), FALSE) AS explicit,
+		(SELECT a.name
+			FROM tracks AS at
+			JOIN track_artists AS ta ON ta.track_id = at.id AND ta.position = 1
+			JOIN artists AS a ON a.id = ta.artist_id
+			WHERE at.entry_id = en.id
+			GROUP BY a.id, a.name
+			ORDER BY COUNT(*) DESC, a.name
+			LIMIT 1
+		) AS primary_artist
+	FROM entries AS en
+	LEFT OUTER JOIN tracks AS t ON t.entry_id = en.id
+	GROUP BY en.id
+) AS s
+WHERE e.id = s.entry_id;
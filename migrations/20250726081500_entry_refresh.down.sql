@@ -0,0 +1,3 @@
+ALTER TABLE entries
+	DROP COLUMN unavailable_since,
+	DROP COLUMN refreshed_at;
@@ -0,0 +1,3 @@
+ALTER TABLE entries
+	ADD COLUMN refreshed_at TIMESTAMPTZ,
+	ADD COLUMN unavailable_since TIMESTAMPTZ;
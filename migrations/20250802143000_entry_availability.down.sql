@@ -0,0 +1,5 @@
+ALTER TABLE tracks DROP COLUMN playable;
+
+ALTER TABLE entries DROP COLUMN unavailable_reason;
+
+DROP TYPE unavailable_reason;
@@ -0,0 +1,38 @@
+CREATE TYPE unavailable_reason AS ENUM ('removed','restricted');
+
+ALTER TABLE entries ADD COLUMN unavailable_reason unavailable_reason;
+
+UPDATE entries SET unavailable_reason = 'removed' WHERE unavailable_since IS NOT NULL;
+
+ALTER TABLE tracks ADD COLUMN playable BOOLEAN NOT NULL DEFAULT TRUE;
+
+-- blobs fetched for a market carry is_playable, others list the available markets
+UPDATE tracks AS t
+SET playable = FALSE
+FROM entries AS e,
+	json_array_elements(e.blob->'tracks'->'items') WITH ORDINALITY AS i(item, number)
+WHERE t.entry_id = e.id
+	AND t.track_number = i.number
+	AND json_typeof(e.blob->'tracks'->'items') = 'array'
+	AND json_typeof(COALESCE(i.item->'track', i.item)) = 'object'
+	AND (
+		COALESCE(i.item->'track', i.item)->>'is_playable' = 'false'
+		OR (
+			json_typeof(COALESCE(i.item->'track', i.item)->'available_markets') = 'array'
+			AND json_array_length(COALESCE(i.item->'track', i.item)->'available_markets') > 0
+			AND NOT (COALESCE(i.item->'track', i.item)->'available_markets')::jsonb ? 'DE'
+		)
+	);
+
+UPDATE entries AS e
+SET unavailable_since = NOW(), unavailable_reason = 'restricted'
+WHERE e.unavailable_since IS NULL
+	AND (
+		(EXISTS (SELECT 1 FROM tracks WHERE entry_id = e.id)
+			AND NOT EXISTS (SELECT 1 FROM tracks WHERE entry_id = e.id AND playable))
+		OR (
+			json_typeof(e.blob->'available_markets') = 'array'
+			AND json_array_length(e.blob->'available_markets') > 0
+			AND NOT (e.blob->'available_markets')::jsonb ? 'DE'
+		)
+	);
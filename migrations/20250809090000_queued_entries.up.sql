@@ -0,0 +1,6 @@
+CREATE TABLE queued_entries(
+	room_id UUID NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+	entry_id UUID NOT NULL REFERENCES entries(id) ON DELETE CASCADE,
+	queued_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+	PRIMARY KEY (room_id, entry_id)
+);
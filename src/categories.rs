@@ -1,6 +1,8 @@
 use sqlx::{PgPool, types::Uuid};
 use strum::{AsRefStr, Display, EnumString};
 
+use crate::playback::Repeat;
+
 pub mod handlers;
 
 #[derive(Debug, sqlx::Type, AsRefStr, EnumString, PartialEq, Display)]
@@ -20,6 +22,10 @@ pub struct Category {
     pub image_url: String,
     pub category_type: CategoryType,
     pub visible: bool,
+    pub series: bool,
+    pub last_entry_id: Option<sqlx::types::Uuid>,
+    pub shuffle: bool,
+    pub repeat: Repeat,
 }
 
 async fn list_all_by_type(
@@ -30,7 +36,7 @@ async fn list_all_by_type(
         Category,
         r#"
         SELECT 
-            c.id, c.name, c.image_url, c.category_type AS "category_type!: CategoryType", c.visible
+            c.id, c.name, c.image_url, c.category_type AS "category_type!: CategoryType", c.visible, c.series, c.last_entry_id, c.shuffle, c.repeat AS "repeat!: Repeat"
         FROM categories AS c
         WHERE c.category_type = ($1::text)::category_type
             AND c.visible = TRUE
@@ -53,7 +59,7 @@ pub async fn list_all(db: &PgPool) -> anyhow::Result<Vec<Category>> {
         Category,
         r#"
         SELECT 
-            id, name, image_url, category_type AS "category_type!: CategoryType", visible
+            id, name, image_url, category_type AS "category_type!: CategoryType", visible, series, last_entry_id, shuffle, repeat AS "repeat!: Repeat"
         FROM categories
         ORDER BY name
         "#,
@@ -64,13 +70,13 @@ pub async fn list_all(db: &PgPool) -> anyhow::Result<Vec<Category>> {
     Ok(result)
 }
 
-async fn get(db: &PgPool, category_id: &str) -> anyhow::Result<Category> {
+pub async fn get(db: &PgPool, category_id: &str) -> anyhow::Result<Category> {
     let id = sqlx::types::Uuid::parse_str(category_id)?;
     let result = sqlx::query_as!(
         Category,
         r#"
         SELECT 
-            id, name, image_url, category_type AS "category_type!: CategoryType", visible
+            id, name, image_url, category_type AS "category_type!: CategoryType", visible, series, last_entry_id, shuffle, repeat AS "repeat!: Repeat"
         FROM categories 
         WHERE id = $1
         "#,
@@ -102,14 +108,18 @@ async fn update(db: &PgPool, category: &Category) -> anyhow::Result<()> {
         r#"
         UPDATE categories
         SET
-            name = $2, image_url = $3, category_type = ($4::text)::category_type, visible = $5
+            name = $2, image_url = $3, category_type = ($4::text)::category_type, visible = $5, series = $6,
+            shuffle = $7, repeat = ($8::text)::repeat_mode
         WHERE id = $1
         "#,
         category.id,
         category.name,
         category.image_url,
         category.category_type.as_ref(),
-        category.visible
+        category.visible,
+        category.series,
+        category.shuffle,
+        category.repeat.as_ref()
     )
     .execute(db)
     .await?;
@@ -125,8 +135,8 @@ async fn create(
 ) -> anyhow::Result<Uuid> {
     let rec = sqlx::query!(
         r#"
-        INSERT INTO categories (name, image_url, category_type)
-        VALUES ($1, $2, ($3::text)::category_type)
+        INSERT INTO categories (name, image_url, category_type, shuffle)
+        VALUES ($1, $2, ($3::text)::category_type, $3 = 'music')
         RETURNING id
         "#,
         name,
@@ -144,7 +154,7 @@ pub async fn admin_search(db: &PgPool, query: &str) -> anyhow::Result<Vec<Catego
         Category,
         r#"
         SELECT 
-            id, name, image_url, category_type AS "category_type!: CategoryType", visible
+            id, name, image_url, category_type AS "category_type!: CategoryType", visible, series, last_entry_id, shuffle, repeat AS "repeat!: Repeat"
         FROM categories
         WHERE name like $1
         ORDER BY name
@@ -167,7 +177,7 @@ pub async fn search(
         Category,
         r#"
         SELECT 
-            c.id, c.name, c.image_url, c.category_type AS "category_type!: CategoryType", c.visible
+            c.id, c.name, c.image_url, c.category_type AS "category_type!: CategoryType", c.visible, c.series, c.last_entry_id, c.shuffle, c.repeat AS "repeat!: Repeat"
         FROM categories AS c
         WHERE c.category_type = ($1::text)::category_type
             AND c.visible = TRUE
@@ -186,3 +196,27 @@ pub async fn search(
 
     Ok(result)
 }
+
+pub async fn set_last_entry(
+    db: &PgPool,
+    category_id: &sqlx::types::Uuid,
+    entry_id: &sqlx::types::Uuid,
+    room_id: &sqlx::types::Uuid,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE categories
+        SET
+            last_entry_id = $2,
+            last_room_id = $3
+        WHERE id = $1 AND series = TRUE
+        "#,
+        category_id,
+        entry_id,
+        room_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
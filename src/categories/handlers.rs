@@ -12,7 +12,9 @@ use serde::Deserialize;
 use crate::{
     categories::CategoryType,
     entries::{self, EntryListModel},
-    errors, states,
+    errors,
+    playback::Repeat,
+    states,
 };
 
 use super::Category;
@@ -81,7 +83,7 @@ pub async fn admin_create(
     .await?;
 
     let mut headers = HeaderMap::new();
-    let path = format!("/admin/categories/{}", id.to_string());
+    let path = format!("/admin/categories/{}", id);
     headers.insert("HX-Redirect", path.parse()?);
     Ok(headers)
 }
@@ -109,6 +111,11 @@ pub struct CategoryEditForm {
     category_type: String,
     #[serde(default)]
     visible: bool,
+    #[serde(default)]
+    series: bool,
+    #[serde(default)]
+    shuffle: bool,
+    repeat: String,
 }
 
 impl TryInto<Category> for CategoryEditForm {
@@ -122,6 +129,10 @@ impl TryInto<Category> for CategoryEditForm {
             image_url: self.image_url,
             category_type: CategoryType::from_str(&self.category_type)?,
             visible: self.visible,
+            series: self.series,
+            last_entry_id: None,
+            shuffle: self.shuffle,
+            repeat: Repeat::from_str(&self.repeat)?,
         })
     }
 }
@@ -133,7 +144,7 @@ pub async fn admin_update(
     let category = category_form.try_into()?;
     super::update(&state.db, &category).await?;
     let mut headers = HeaderMap::new();
-    let path = format!("/admin/categories/{}", category.id.to_string());
+    let path = format!("/admin/categories/{}", category.id);
     headers.insert("HX-Redirect", path.parse()?);
     Ok(headers)
 }
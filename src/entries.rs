@@ -1,9 +1,35 @@
 use std::collections::HashMap;
+use std::time::Duration;
 
-use sqlx::{PgPool, types::Uuid};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Deserialize;
+use sqlx::{PgConnection, PgPool, types::Uuid};
 use strum::{AsRefStr, Display, EnumString};
+use tracing::{error, info, warn};
+
+use crate::{playback::Repeat, states};
 
 pub mod handlers;
+pub mod source;
+
+#[derive(Debug, Clone, Copy, sqlx::Type, AsRefStr, EnumString, PartialEq, Display)]
+#[sqlx(type_name = "unavailable_reason", rename_all = "snake_case")]
+pub enum UnavailableReason {
+    #[strum(serialize = "removed")]
+    Removed,
+
+    #[strum(serialize = "restricted")]
+    Restricted,
+}
+
+impl UnavailableReason {
+    pub fn description(&self) -> &'static str {
+        match self {
+            UnavailableReason::Removed => "removed from Spotify",
+            UnavailableReason::Restricted => "not playable in our market",
+        }
+    }
+}
 
 #[derive(Debug, sqlx::Type, AsRefStr, EnumString, PartialEq, Display, Clone)]
 #[sqlx(type_name = "entry_type", rename_all = "lowercase")]
@@ -18,18 +44,23 @@ pub enum EntryType {
 async fn list_all_visible_by_category(
     db: &PgPool,
     category_id: &str,
+    hide_explicit: bool,
 ) -> anyhow::Result<Vec<EntryListModel>> {
     let id = sqlx::types::Uuid::parse_str(category_id)?;
     let result = sqlx::query_as!(
         EntryListModel,
         r#"
         SELECT 
-            id, name, image_url, visible, play_count as "play_count!"
-        FROM entries
-        WHERE category_id = $1 AND visible = TRUE
-        ORDER BY name
+            e.id, e.name, e.image_url, e.visible, e.play_count as "play_count!",
+            e.release_date, e.duration_ms, e.track_count, e.primary_artist, e.explicit
+        FROM entries AS e
+        JOIN categories AS c ON e.category_id = c.id
+        WHERE e.category_id = $1 AND e.visible = TRUE AND e.unavailable_since IS NULL
+            AND NOT ($2 AND e.explicit)
+        ORDER BY CASE WHEN c.series THEN e.episode_number END, e.name
         "#,
-        id
+        id,
+        hide_explicit
     )
     .fetch_all(db)
     .await?;
@@ -46,7 +77,8 @@ pub async fn list_all_by_category(
         EntryListModel,
         r#"
         SELECT 
-            id, name, image_url, visible, play_count as "play_count!"
+            id, name, image_url, visible, play_count as "play_count!",
+            release_date, duration_ms, track_count, primary_artist, explicit
         FROM entries
         WHERE category_id = $1
         ORDER BY name
@@ -59,6 +91,15 @@ pub async fn list_all_by_category(
     Ok(result)
 }
 
+#[derive(Debug, Default, Deserialize, AsRefStr, EnumString, PartialEq, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum EntrySort {
+    #[default]
+    Name,
+    ReleaseDate,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct CategoryListModel {
     pub name: String,
@@ -72,16 +113,44 @@ pub struct EntryListModel {
     pub image_url: String,
     pub visible: bool,
     pub play_count: i16,
+    pub release_date: Option<NaiveDate>,
+    pub duration_ms: i64,
+    pub track_count: i32,
+    pub primary_artist: Option<String>,
+    pub explicit: bool,
 }
 
-async fn list_all(db: &PgPool) -> anyhow::Result<Vec<CategoryListModel>> {
+impl EntryListModel {
+    pub fn runtime(&self) -> String {
+        runtime(self.duration_ms)
+    }
+}
+
+fn runtime(duration_ms: i64) -> String {
+    let minutes = duration_ms / 60_000;
+    if minutes < 60 {
+        format!("{} min", minutes)
+    } else {
+        format!("{} h {} min", minutes / 60, minutes % 60)
+    }
+}
+
+async fn list_all(
+    db: &PgPool,
+    sort: &EntrySort,
+    hide_explicit: bool,
+) -> anyhow::Result<Vec<CategoryListModel>> {
     let result = sqlx::query!(
         r#"
-        SELECT e.id AS "entry_id", e.name AS "entry_name", e.image_url AS "entry_image_url", e.visible AS "entry_visible", e.play_count AS "entry_play_count!", c.id AS "category_id?", c.name AS "catgegory_name?"
+        SELECT e.id AS "entry_id", e.name AS "entry_name", e.image_url AS "entry_image_url", e.visible AS "entry_visible", e.play_count AS "entry_play_count!", c.id AS "category_id?", c.name AS "catgegory_name?",
+            e.release_date, e.duration_ms, e.track_count, e.primary_artist, e.explicit
         FROM entries AS e
         LEFT OUTER JOIN categories AS c ON e.category_id = c.id
-        ORDER BY e.name
-        "#)
+        WHERE NOT ($2 AND e.explicit)
+        ORDER BY CASE WHEN $1 THEN e.release_date END DESC NULLS LAST, e.name
+        "#,
+        *sort == EntrySort::ReleaseDate,
+        hide_explicit)
         .fetch_all(db)
         .await?;
 
@@ -97,6 +166,11 @@ async fn list_all(db: &PgPool) -> anyhow::Result<Vec<CategoryListModel>> {
                         image_url: r.entry_image_url.clone(),
                         visible: r.entry_visible,
                         play_count: r.entry_play_count,
+                        release_date: r.release_date,
+                        duration_ms: r.duration_ms,
+                        track_count: r.track_count,
+                        primary_artist: r.primary_artist.clone(),
+                        explicit: r.explicit,
                     }),
                     None => {
                         acc.insert(
@@ -109,10 +183,14 @@ async fn list_all(db: &PgPool) -> anyhow::Result<Vec<CategoryListModel>> {
                                     image_url: r.entry_image_url.clone(),
                                     visible: r.entry_visible,
                                     play_count: r.entry_play_count,
+                                    release_date: r.release_date,
+                                    duration_ms: r.duration_ms,
+                                    track_count: r.track_count,
+                                    primary_artist: r.primary_artist.clone(),
+                                    explicit: r.explicit,
                                 }],
                             },
                         );
-                        ()
                     }
                 };
 
@@ -141,6 +219,40 @@ pub struct EntryEditModel {
     pub category_id: Option<sqlx::types::Uuid>,
     pub visible: bool,
     pub spotify_url: String,
+    pub episode_number: Option<i32>,
+    pub shuffle: Option<bool>,
+    pub repeat: Option<Repeat>,
+    pub spoken_name: Option<String>,
+    pub release_date: Option<NaiveDate>,
+    pub duration_ms: i64,
+    pub track_count: i32,
+    pub primary_artist: Option<String>,
+    pub explicit: bool,
+    pub refreshed_at: Option<DateTime<Utc>>,
+    pub unavailable_since: Option<DateTime<Utc>>,
+    pub unavailable_reason: Option<UnavailableReason>,
+}
+
+/// the fields the edit form may change, the source and everything derived from it stay as they are
+#[derive(Debug)]
+pub struct EntryUpdateModel {
+    pub id: sqlx::types::Uuid,
+    pub name: String,
+    pub image_url: String,
+    pub play_count: i16,
+    pub category_id: Option<sqlx::types::Uuid>,
+    pub visible: bool,
+    pub episode_number: Option<i32>,
+    pub shuffle: Option<bool>,
+    pub repeat: Option<Repeat>,
+    pub spoken_name: Option<String>,
+}
+
+impl EntryEditModel {
+    /// what announcements say, some names read badly when spoken
+    pub fn spoken_name(&self) -> &str {
+        self.spoken_name.as_deref().unwrap_or(&self.name)
+    }
 }
 
 pub async fn get(db: &PgPool, entry_id: &str) -> anyhow::Result<EntryEditModel> {
@@ -148,7 +260,10 @@ pub async fn get(db: &PgPool, entry_id: &str) -> anyhow::Result<EntryEditModel>
     let result = sqlx::query_as!(
         EntryEditModel,
         r#"
-        SELECT id, name, image_url, entry_type AS "entry_type!: EntryType", spotify_uri, spotify_id, play_count AS "play_count!", blob, category_id, visible, spotify_url
+        SELECT id, name, image_url, entry_type AS "entry_type!: EntryType", spotify_uri, spotify_id, play_count AS "play_count!", blob, category_id, visible, spotify_url, episode_number,
+            shuffle, repeat AS "repeat: Repeat", spoken_name,
+            release_date, duration_ms, track_count, primary_artist, explicit,
+            refreshed_at, unavailable_since, unavailable_reason AS "unavailable_reason: UnavailableReason"
         FROM entries
         WHERE id = $1
         "#,
@@ -160,32 +275,32 @@ pub async fn get(db: &PgPool, entry_id: &str) -> anyhow::Result<EntryEditModel>
     Ok(result)
 }
 
-async fn update(db: &PgPool, entry: &EntryEditModel) -> anyhow::Result<()> {
+async fn update(db: &PgPool, entry: &EntryUpdateModel) -> anyhow::Result<()> {
     sqlx::query!(
         r#"
         UPDATE entries
         SET
             name = $2,
             image_url = $3,
-            entry_type = ($4::text)::entry_type,
-            spotify_uri = $5,
-            spotify_id = $6,
-            play_count = $7,
-            blob = $8,
-            category_id = $9,
-            visible = $10
+            play_count = $4,
+            category_id = $5,
+            visible = $6,
+            episode_number = $7,
+            shuffle = $8,
+            repeat = ($9::text)::repeat_mode,
+            spoken_name = $10
         WHERE id = $1
         "#,
         entry.id,
         entry.name,
         entry.image_url,
-        entry.entry_type.as_ref(),
-        entry.spotify_uri,
-        entry.spotify_id,
         entry.play_count,
-        entry.blob,
         entry.category_id,
-        entry.visible
+        entry.visible,
+        entry.episode_number,
+        entry.shuffle,
+        entry.repeat.as_ref().map(|r| r.as_ref()),
+        entry.spoken_name
     )
     .execute(db)
     .await?;
@@ -220,13 +335,15 @@ pub struct EntryCreateModel {
     pub blob: serde_json::Value,
     pub visible: bool,
     pub category_id: Option<sqlx::types::Uuid>,
+    pub episode_number: Option<i32>,
 }
 
 pub async fn create(db: &PgPool, entry: EntryCreateModel) -> anyhow::Result<Uuid> {
+    let mut tx = db.begin().await?;
     let rec = sqlx::query!(
         r#"
-        INSERT INTO entries (name, image_url, entry_type, spotify_uri, spotify_id, play_count, blob, visible, category_id, spotify_url)
-        VALUES ($1, $2, ($3::text)::entry_type, $4, $5, $6, $7, $8, $9, $10)
+        INSERT INTO entries (name, image_url, entry_type, spotify_uri, spotify_id, play_count, blob, visible, category_id, spotify_url, episode_number)
+        VALUES ($1, $2, ($3::text)::entry_type, $4, $5, $6, $7, $8, $9, $10, $11)
         RETURNING id
         "#,
         entry.name,
@@ -238,13 +355,164 @@ pub async fn create(db: &PgPool, entry: EntryCreateModel) -> anyhow::Result<Uuid
         entry.blob,
         entry.visible,
         entry.category_id,
-        entry.spotify_url
+        entry.spotify_url,
+        entry.episode_number
     )
-    .fetch_one(db)
+    .fetch_one(&mut *tx)
     .await?;
+    sync_metadata(&mut tx, &rec.id).await?;
+    tx.commit().await?;
     Ok(rec.id)
 }
 
+/// rebuilds the tracks, artists and typed metadata of an entry from its spotify blob,
+/// album blobs list the tracks directly while playlist blobs wrap them in an item.
+/// an entry without a track playable in our market counts as restricted
+async fn sync_metadata(conn: &mut PgConnection, entry_id: &Uuid) -> anyhow::Result<()> {
+    let market = source::market_code();
+    sqlx::query!(
+        r#"
+        DELETE FROM tracks
+        WHERE entry_id = $1
+        "#,
+        entry_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        WITH items AS (
+            SELECT COALESCE(t.item->'track', t.item) AS track
+            FROM entries AS e,
+                json_array_elements(e.blob->'tracks'->'items') AS t(item)
+            WHERE e.id = $1 AND json_typeof(e.blob->'tracks'->'items') = 'array'
+        )
+        INSERT INTO artists (spotify_id, name)
+        SELECT DISTINCT ON (a.artist->>'id') a.artist->>'id', COALESCE(a.artist->>'name', '')
+        FROM items AS i,
+            json_array_elements(i.track->'artists') AS a(artist)
+        WHERE json_typeof(i.track) = 'object'
+            AND json_typeof(i.track->'artists') = 'array'
+            AND a.artist->>'id' IS NOT NULL
+        ON CONFLICT (spotify_id) DO UPDATE
+        SET name = EXCLUDED.name
+        "#,
+        entry_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        WITH items AS (
+            SELECT t.number::int AS track_number, COALESCE(t.item->'track', t.item) AS track
+            FROM entries AS e,
+                json_array_elements(e.blob->'tracks'->'items') WITH ORDINALITY AS t(item, number)
+            WHERE e.id = $1 AND json_typeof(e.blob->'tracks'->'items') = 'array'
+        )
+        INSERT INTO tracks (entry_id, track_number, spotify_id, name, duration_ms, explicit, playable)
+        SELECT $1, i.track_number, i.track->>'id', COALESCE(i.track->>'name', ''), COALESCE((i.track->>'duration_ms')::int, 0),
+            COALESCE(i.track->>'explicit', '') = 'true',
+            COALESCE(i.track->>'is_playable', '') <> 'false'
+                AND NOT COALESCE(
+                    $2::text IS NOT NULL
+                    AND json_typeof(i.track->'available_markets') = 'array'
+                    AND json_array_length(i.track->'available_markets') > 0
+                    AND NOT (i.track->'available_markets')::jsonb ? $2,
+                    FALSE
+                )
+        FROM items AS i
+        WHERE json_typeof(i.track) = 'object' AND i.track->>'id' IS NOT NULL
+        "#,
+        entry_id,
+        market
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        WITH items AS (
+            SELECT t.number::int AS track_number, COALESCE(t.item->'track', t.item) AS track
+            FROM entries AS e,
+                json_array_elements(e.blob->'tracks'->'items') WITH ORDINALITY AS t(item, number)
+            WHERE e.id = $1 AND json_typeof(e.blob->'tracks'->'items') = 'array'
+        )
+        INSERT INTO track_artists (track_id, artist_id, position)
+        SELECT t.id, ar.id, a.position::int
+        FROM items AS i
+        JOIN tracks AS t ON t.entry_id = $1 AND t.track_number = i.track_number,
+            json_array_elements(i.track->'artists') WITH ORDINALITY AS a(artist, position)
+        JOIN artists AS ar ON ar.spotify_id = a.artist->>'id'
+        WHERE json_typeof(i.track->'artists') = 'array'
+        "#,
+        entry_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    // spotify reports the release date with year, month or day precision
+    sqlx::query!(
+        r#"
+        UPDATE entries AS e
+        SET
+            track_count = s.track_count,
+            duration_ms = s.duration_ms,
+            explicit = s.explicit,
+            release_date = CASE
+                WHEN e.blob->>'release_date' ~ '^\d{4}-\d{2}-\d{2}$' THEN to_date(e.blob->>'release_date', 'YYYY-MM-DD')
+                WHEN e.blob->>'release_date' ~ '^\d{4}-\d{2}$' THEN to_date(e.blob->>'release_date', 'YYYY-MM')
+                WHEN e.blob->>'release_date' ~ '^\d{4}$' THEN to_date(e.blob->>'release_date', 'YYYY')
+            END,
+            primary_artist = COALESCE(e.blob->'artists'->0->>'name', s.primary_artist),
+            unavailable_since = CASE
+                WHEN s.restricted THEN COALESCE(e.unavailable_since, NOW())
+                WHEN e.unavailable_reason = 'restricted' THEN NULL
+                ELSE e.unavailable_since
+            END,
+            unavailable_reason = CASE
+                WHEN s.restricted THEN 'restricted'
+                WHEN e.unavailable_reason = 'restricted' THEN NULL
+                ELSE e.unavailable_reason
+            END
+        FROM (
+            SELECT
+                COUNT(*)::int AS track_count,
+                COALESCE(SUM(t.duration_ms), 0)::bigint AS duration_ms,
+                COALESCE(BOOL_OR(t.explicit), FALSE) AS explicit,
+                (COUNT(*) > 0 AND NOT BOOL_OR(t.playable))
+                    OR EXISTS (SELECT 1
+                        FROM entries AS m
+                        WHERE m.id = $1
+                            AND $2::text IS NOT NULL
+                            AND json_typeof(m.blob->'available_markets') = 'array'
+                            AND json_array_length(m.blob->'available_markets') > 0
+                            AND NOT (m.blob->'available_markets')::jsonb ? $2
+                    ) AS restricted,
+                (SELECT a.name
+                    FROM tracks AS at
+                    JOIN track_artists AS ta ON ta.track_id = at.id AND ta.position = 1
+                    JOIN artists AS a ON a.id = ta.artist_id
+                    WHERE at.entry_id = $1
+                    GROUP BY a.id, a.name
+                    ORDER BY COUNT(*) DESC, a.name
+                    LIMIT 1
+                ) AS primary_artist
+            FROM tracks AS t
+            WHERE t.entry_id = $1
+        ) AS s
+        WHERE e.id = $1
+        "#,
+        entry_id,
+        market
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
 pub async fn increment_play_count(db: &PgPool, entry_id: &str) -> anyhow::Result<()> {
     let id = sqlx::types::Uuid::parse_str(entry_id)?;
     sqlx::query!(
@@ -262,11 +530,85 @@ pub async fn increment_play_count(db: &PgPool, entry_id: &str) -> anyhow::Result
     Ok(())
 }
 
+/// queued entries only count as played once the room reaches them
+pub async fn queue(db: &PgPool, room_id: &Uuid, entry_id: &Uuid) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO queued_entries (room_id, entry_id)
+        VALUES ($1, $2)
+        ON CONFLICT (room_id, entry_id) DO UPDATE
+        SET queued_at = NOW()
+        "#,
+        room_id,
+        entry_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// starting an entry replaces the queue of the room
+pub async fn clear_queued(db: &PgPool, room_id: &Uuid) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        DELETE FROM queued_entries
+        WHERE room_id = $1
+        "#,
+        room_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct QueuedEntry {
+    pub id: Uuid,
+    pub name: String,
+    pub category_id: Option<Uuid>,
+}
+
+/// the queued entry the room is playing right now, it is removed from the queue on the way
+pub async fn take_queued(
+    db: &PgPool,
+    room_id: &Uuid,
+    media_content_id: &str,
+) -> anyhow::Result<Option<QueuedEntry>> {
+    let track_id = spotify_id(media_content_id);
+    let result = sqlx::query_as!(
+        QueuedEntry,
+        r#"
+        DELETE FROM queued_entries AS q
+        USING entries AS e
+        WHERE q.room_id = $1
+            AND q.entry_id = e.id
+            AND q.queued_at > NOW() - INTERVAL '1 day'
+            AND (e.spotify_uri = $2
+                OR EXISTS (SELECT 1
+                    FROM tracks AS t
+                    WHERE t.entry_id = e.id AND t.spotify_id = $3
+                )
+            )
+        RETURNING e.id, e.name, e.category_id
+        "#,
+        room_id,
+        media_content_id,
+        track_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(result)
+}
+
 pub async fn admin_search(db: &PgPool, query: &str) -> anyhow::Result<Vec<CategoryListModel>> {
     let query = format!("%{}%", query);
     let result = sqlx::query!(
         r#"
-        SELECT e.id AS "entry_id", e.name AS "entry_name", e.image_url AS "entry_image_url", e.visible AS "entry_visible", e.play_count AS "entry_play_count!", c.id AS "category_id?", c.name AS "catgegory_name?"
+        SELECT e.id AS "entry_id", e.name AS "entry_name", e.image_url AS "entry_image_url", e.visible AS "entry_visible", e.play_count AS "entry_play_count!", c.id AS "category_id?", c.name AS "catgegory_name?",
+            e.release_date, e.duration_ms, e.track_count, e.primary_artist, e.explicit
         FROM entries AS e
         LEFT OUTER JOIN categories AS c ON e.category_id = c.id
         WHERE e.name LIKE $1
@@ -288,6 +630,11 @@ pub async fn admin_search(db: &PgPool, query: &str) -> anyhow::Result<Vec<Catego
                         image_url: r.entry_image_url.clone(),
                         visible: r.entry_visible,
                         play_count: r.entry_play_count,
+                        release_date: r.release_date,
+                        duration_ms: r.duration_ms,
+                        track_count: r.track_count,
+                        primary_artist: r.primary_artist.clone(),
+                        explicit: r.explicit,
                     }),
                     None => {
                         acc.insert(
@@ -300,10 +647,14 @@ pub async fn admin_search(db: &PgPool, query: &str) -> anyhow::Result<Vec<Catego
                                     image_url: r.entry_image_url.clone(),
                                     visible: r.entry_visible,
                                     play_count: r.entry_play_count,
+                                    release_date: r.release_date,
+                                    duration_ms: r.duration_ms,
+                                    track_count: r.track_count,
+                                    primary_artist: r.primary_artist.clone(),
+                                    explicit: r.explicit,
                                 }],
                             },
                         );
-                        ()
                     }
                 };
 
@@ -323,6 +674,7 @@ pub async fn search(
     db: &PgPool,
     category_id: &str,
     query: &str,
+    hide_explicit: bool,
 ) -> anyhow::Result<Vec<EntryListModel>> {
     let query = format!("%{}%", query);
     let id = sqlx::types::Uuid::parse_str(category_id)?;
@@ -330,16 +682,472 @@ pub async fn search(
         EntryListModel,
         r#"
         SELECT 
-            id, name, image_url, visible, play_count as "play_count!"
-        FROM entries
-        WHERE name LIKE $1 AND visible = TRUE AND category_id = $2
-        ORDER BY name
+            e.id, e.name, e.image_url, e.visible, e.play_count as "play_count!",
+            e.release_date, e.duration_ms, e.track_count, e.primary_artist, e.explicit
+        FROM entries AS e
+        JOIN categories AS c ON e.category_id = c.id
+        WHERE e.name LIKE $1 AND e.visible = TRUE AND e.unavailable_since IS NULL AND e.category_id = $2
+            AND NOT ($3 AND e.explicit)
+        ORDER BY CASE WHEN c.series THEN e.episode_number END, e.name
         "#,
         query,
-        id
+        id,
+        hide_explicit
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(result)
+}
+
+/// home assistant reports either the played context or the current track as uri or url
+pub fn spotify_id(media_content_id: &str) -> &str {
+    media_content_id
+        .split('?')
+        .next()
+        .and_then(|s| s.rsplit([':', '/']).next())
+        .unwrap_or_default()
+}
+
+pub async fn find_by_media_content_id(
+    db: &PgPool,
+    media_content_id: &str,
+) -> anyhow::Result<Option<EntryListModel>> {
+    let track_id = spotify_id(media_content_id);
+    let result = sqlx::query_as!(
+        EntryListModel,
+        r#"
+        SELECT 
+            id, name, image_url, visible, play_count as "play_count!",
+            release_date, duration_ms, track_count, primary_artist, explicit
+        FROM entries
+        WHERE spotify_uri = $1
+            OR EXISTS (SELECT 1
+                    FROM tracks AS t
+                    WHERE t.entry_id = entries.id AND t.spotify_id = $2
+                )
+        ORDER BY play_count DESC
+        LIMIT 1
+        "#,
+        media_content_id,
+        track_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(result)
+}
+
+#[derive(Debug)]
+pub struct AudiobookTrack {
+    pub entry_id: sqlx::types::Uuid,
+    pub track_number: i32,
+    pub track_count: i64,
+    pub duration_ms: i32,
+}
+
+pub async fn find_audiobook_track(
+    db: &PgPool,
+    media_content_id: &str,
+) -> anyhow::Result<Option<AudiobookTrack>> {
+    let track_id = spotify_id(media_content_id);
+    let result = sqlx::query_as!(
+        AudiobookTrack,
+        r#"
+        SELECT
+            e.id AS "entry_id",
+            t.track_number,
+            (SELECT COUNT(*) FROM tracks WHERE entry_id = e.id) AS "track_count!",
+            t.duration_ms
+        FROM entries AS e
+        JOIN categories AS c ON e.category_id = c.id
+        JOIN tracks AS t ON t.entry_id = e.id
+        WHERE c.category_type = 'audiobook'
+            AND e.entry_type = 'album'
+            AND t.spotify_id = $1
+        LIMIT 1
+        "#,
+        track_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(result)
+}
+
+#[derive(Debug)]
+pub struct TrackModel {
+    pub number: i32,
+    pub name: String,
+    pub duration_ms: i32,
+}
+
+impl TrackModel {
+    pub fn duration(&self) -> String {
+        let seconds = self.duration_ms / 1000;
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+/// playlists are played as a whole, only albums offer their tracks
+pub async fn list_tracks(
+    db: &PgPool,
+    entry_id: &sqlx::types::Uuid,
+) -> anyhow::Result<Vec<TrackModel>> {
+    let result = sqlx::query_as!(
+        TrackModel,
+        r#"
+        SELECT t.track_number AS "number", t.name, t.duration_ms
+        FROM entries AS e
+        JOIN tracks AS t ON t.entry_id = e.id
+        WHERE e.id = $1
+            AND e.entry_type = 'album'
+        ORDER BY t.track_number
+        "#,
+        entry_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(result)
+}
+
+#[derive(Debug)]
+pub struct EntryStats {
+    pub track_count: i64,
+    pub duration_ms: i64,
+    pub artists: Vec<String>,
+}
+
+impl EntryStats {
+    pub fn runtime(&self) -> String {
+        runtime(self.duration_ms)
+    }
+}
+
+/// artists are ordered by how many tracks they appear on
+pub async fn stats(db: &PgPool, entry_id: &sqlx::types::Uuid) -> anyhow::Result<EntryStats> {
+    let result = sqlx::query_as!(
+        EntryStats,
+        r#"
+        SELECT
+            COUNT(*) AS "track_count!",
+            COALESCE(SUM(t.duration_ms), 0)::bigint AS "duration_ms!",
+            ARRAY(
+                SELECT a.name
+                FROM tracks AS at
+                JOIN track_artists AS ta ON ta.track_id = at.id
+                JOIN artists AS a ON a.id = ta.artist_id
+                WHERE at.entry_id = $1
+                GROUP BY a.id, a.name
+                ORDER BY COUNT(*) DESC, a.name
+                LIMIT 5
+            ) AS "artists!"
+        FROM tracks AS t
+        WHERE t.entry_id = $1
+        "#,
+        entry_id
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(result)
+}
+
+#[derive(Debug)]
+pub struct PlaybackModes {
+    pub shuffle: bool,
+    pub repeat: Repeat,
+}
+
+/// an entry without its own modes uses the defaults of its category
+pub async fn playback_modes(
+    db: &PgPool,
+    entry_id: &sqlx::types::Uuid,
+) -> anyhow::Result<PlaybackModes> {
+    let result = sqlx::query_as!(
+        PlaybackModes,
+        r#"
+        SELECT
+            COALESCE(e.shuffle, c.shuffle, FALSE) AS "shuffle!",
+            COALESCE(e.repeat, c.repeat, 'off') AS "repeat!: Repeat"
+        FROM entries AS e
+        LEFT OUTER JOIN categories AS c ON e.category_id = c.id
+        WHERE e.id = $1
+        "#,
+        entry_id
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(result)
+}
+
+/// series names the episode either after a keyword like "Folge 12" or as a leading number like "012/Titel",
+/// the first keyword followed by a number wins just like in the backfill of the series migration
+pub fn parse_episode_number(name: &str) -> Option<i32> {
+    let lowercase = name.to_lowercase();
+    ["folge", "episode", "teil"]
+        .iter()
+        .flat_map(|keyword| {
+            lowercase
+                .match_indices(keyword)
+                .filter_map(|(i, _)| Some((i, leading_number(&lowercase[i + keyword.len()..])?)))
+        })
+        .min_by_key(|(i, _)| *i)
+        .map(|(_, number)| number)
+        .or_else(|| leading_number(&lowercase))
+}
+
+fn leading_number(text: &str) -> Option<i32> {
+    let number = text
+        .trim_start()
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect::<String>();
+    number.parse().ok()
+}
+
+pub async fn next_episode(
+    db: &PgPool,
+    category_id: &str,
+    hide_explicit: bool,
+) -> anyhow::Result<Option<EntryListModel>> {
+    let id = sqlx::types::Uuid::parse_str(category_id)?;
+    let result = sqlx::query_as!(
+        EntryListModel,
+        r#"
+        SELECT 
+            e.id, e.name, e.image_url, e.visible, e.play_count as "play_count!",
+            e.release_date, e.duration_ms, e.track_count, e.primary_artist, e.explicit
+        FROM entries AS e
+        JOIN categories AS c ON e.category_id = c.id
+        LEFT OUTER JOIN entries AS last ON c.last_entry_id = last.id
+        WHERE e.category_id = $1
+            AND e.visible = TRUE
+            AND e.unavailable_since IS NULL
+            AND c.series = TRUE
+            AND e.episode_number > COALESCE(last.episode_number, 0)
+            AND NOT ($2 AND e.explicit)
+        ORDER BY e.episode_number, e.name
+        LIMIT 1
+        "#,
+        id,
+        hide_explicit
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(result)
+}
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const REFRESH_THROTTLE: Duration = Duration::from_millis(200);
+
+#[derive(Debug)]
+struct StaleEntry {
+    id: Uuid,
+    name: String,
+    entry_type: EntryType,
+    spotify_id: String,
+}
+
+/// entries that were not refreshed within the last day, the oldest first
+async fn list_stale(db: &PgPool) -> anyhow::Result<Vec<StaleEntry>> {
+    let result = sqlx::query_as!(
+        StaleEntry,
+        r#"
+        SELECT id, name, entry_type AS "entry_type!: EntryType", spotify_id
+        FROM entries
+        WHERE refreshed_at IS NULL OR refreshed_at < NOW() - INTERVAL '1 day'
+        ORDER BY refreshed_at NULLS FIRST
+        "#
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(result)
+}
+
+/// replaces the spotify data of an entry, its id, category, visibility and play stats stay
+pub async fn update_source(
+    db: &PgPool,
+    entry_id: &Uuid,
+    source: &source::Source,
+) -> anyhow::Result<()> {
+    let mut tx = db.begin().await?;
+    // saved positions point into the old track list, so a new source starts from the beginning
+    sqlx::query!(
+        r#"
+        DELETE FROM playback_positions AS p
+        USING entries AS e
+        WHERE p.entry_id = e.id AND e.id = $1 AND e.spotify_id <> $2
+        "#,
+        entry_id,
+        source.spotify_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE entries
+        SET
+            name = $2,
+            image_url = $3,
+            entry_type = ($4::text)::entry_type,
+            spotify_uri = $5,
+            spotify_id = $6,
+            blob = $7,
+            spotify_url = $8,
+            refreshed_at = NOW(),
+            unavailable_since = NULL,
+            unavailable_reason = NULL
+        WHERE id = $1
+        "#,
+        entry_id,
+        source.name,
+        source.image_url,
+        source.entry_type.as_ref(),
+        source.spotify_uri,
+        source.spotify_id,
+        source.blob,
+        source.spotify_url
+    )
+    .execute(&mut *tx)
+    .await?;
+    sync_metadata(&mut tx, entry_id).await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+async fn mark_unavailable(
+    db: &PgPool,
+    entry_id: &Uuid,
+    reason: UnavailableReason,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE entries
+        SET
+            unavailable_since = COALESCE(unavailable_since, NOW()),
+            unavailable_reason = ($2::text)::unavailable_reason
+        WHERE id = $1
+        "#,
+        entry_id,
+        reason.as_ref()
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+async fn mark_refreshed(db: &PgPool, entry_id: &Uuid) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE entries
+        SET refreshed_at = NOW()
+        WHERE id = $1
+        "#,
+        entry_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+async fn refresh_stale(state: &states::AppState) -> anyhow::Result<()> {
+    for entry in list_stale(&state.db).await? {
+        match source::fetch(&state.spotify, &entry.entry_type, &entry.spotify_id).await {
+            Ok(source) => update_source(&state.db, &entry.id, &source).await?,
+            Err(err) if source::is_gone(&err) => {
+                warn!("{} is no longer available on spotify", entry.name);
+                mark_unavailable(&state.db, &entry.id, UnavailableReason::Removed).await?;
+                mark_refreshed(&state.db, &entry.id).await?;
+            }
+            Err(err) => error!("could not refresh {}: {}", entry.name, err),
+        }
+        tokio::time::sleep(REFRESH_THROTTLE).await;
+    }
+    Ok(())
+}
+
+/// a failed start is no proof, so only a lookup that spotify answers with 404 flags the entry
+pub async fn check_source(state: &states::AppState, entry: &EntryEditModel) {
+    let checked = match source::fetch(&state.spotify, &entry.entry_type, &entry.spotify_id).await {
+        Err(err) if source::is_gone(&err) => {
+            warn!("{} is no longer available on spotify", entry.name);
+            mark_unavailable(&state.db, &entry.id, UnavailableReason::Removed).await
+        }
+        Err(err) => Err(err),
+        Ok(_) => Ok(()),
+    };
+    if let Err(err) = checked {
+        error!("could not check {} on spotify: {}", entry.name, err);
+    }
+}
+
+/// keeps names, cover art and track lists in line with spotify and flags pulled entries
+pub async fn refresh_metadata(state: states::AppState) {
+    info!("refreshing entry metadata");
+    let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(err) = refresh_stale(&state).await {
+            error!("could not refresh entry metadata: {}", err);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BrokenEntryModel {
+    pub id: Uuid,
+    pub name: String,
+    pub image_url: String,
+    pub spotify_id: String,
+    pub primary_artist: Option<String>,
+    pub play_count: i16,
+    pub unavailable_since: DateTime<Utc>,
+    pub unavailable_reason: UnavailableReason,
+}
+
+pub async fn list_unavailable(db: &PgPool) -> anyhow::Result<Vec<BrokenEntryModel>> {
+    let result = sqlx::query_as!(
+        BrokenEntryModel,
+        r#"
+        SELECT
+            id, name, image_url, spotify_id, primary_artist, play_count AS "play_count!",
+            unavailable_since AS "unavailable_since!",
+            COALESCE(unavailable_reason, 'removed') AS "unavailable_reason!: UnavailableReason"
+        FROM entries
+        WHERE unavailable_since IS NOT NULL
+        ORDER BY play_count DESC, name
+        "#
     )
     .fetch_all(db)
     .await?;
 
     Ok(result)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::parse_episode_number;
+
+    #[test]
+    fn episode_number_after_a_keyword() {
+        assert_eq!(parse_episode_number("Folge 12"), Some(12));
+    }
+
+    #[test]
+    fn episode_number_as_a_leading_number() {
+        assert_eq!(parse_episode_number("012 – Title"), Some(12));
+    }
+
+    #[test]
+    fn no_episode_number_without_a_number() {
+        assert_eq!(parse_episode_number("Das Geheimnis der Burg"), None);
+    }
+}
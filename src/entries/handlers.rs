@@ -1,6 +1,5 @@
 use std::str::FromStr;
 
-use anyhow::Result;
 use askama::Template;
 use axum::{
     Form,
@@ -8,26 +7,27 @@ use axum::{
     http::HeaderMap,
     response::{Html, IntoResponse},
 };
-use rspotify::{
-    ClientCredsSpotify,
-    model::{AlbumId, Id, Image, PlaylistId},
-    prelude::BaseClient,
-};
+use axum_extra::extract::Query;
+use rspotify::ClientCredsSpotify;
 use serde::Deserialize;
-use serde_json::json;
 use serde_with::NoneAsEmptyString;
 use serde_with::serde_as;
 use sqlx::types::Uuid;
-use strum::Display;
+use tracing::{info, warn};
 use url::Url;
 
 use crate::{
-    MARKET,
     categories::{self, CategoryType},
-    errors, states, with_height,
+    errors,
+    playback::Repeat,
+    rooms::{self, Room},
+    states,
 };
 
-use super::{CategoryListModel, EntryCreateModel, EntryEditModel, EntryListModel, EntryType};
+use super::{
+    CategoryListModel, EntryCreateModel, EntryEditModel, EntryListModel, EntrySort,
+    EntryUpdateModel, source,
+};
 
 #[derive(Template)]
 #[template(path = "entries.html")]
@@ -35,6 +35,8 @@ struct EntriesTemplate {
     category_id: String,
     category_type: CategoryType,
     entries: Vec<EntryListModel>,
+    next_entry: Option<EntryListModel>,
+    next_room: Option<Room>,
 }
 
 pub async fn list(
@@ -42,25 +44,31 @@ pub async fn list(
     State(_state): State<states::AppState>,
 ) -> Result<impl IntoResponse, errors::AppError> {
     let category_type = CategoryType::from_str(&category)?;
-    let entries = super::list_all_visible_by_category(&_state.db, &category_id).await?;
+    let entries =
+        super::list_all_visible_by_category(&_state.db, &category_id, _state.hide_explicit).await?;
+    let next_entry = super::next_episode(&_state.db, &category_id, _state.hide_explicit).await?;
+    // the next episode starts with one tap in the room the last one was played in
+    let next_room = _state
+        .availability
+        .filter(
+            rooms::last_used(&_state.db, &category_id)
+                .await?
+                .into_iter()
+                .collect(),
+        )
+        .pop();
     Ok(Html(
         EntriesTemplate {
             category_id,
             category_type,
             entries,
+            next_entry,
+            next_room,
         }
         .render()?,
     ))
 }
 
-#[derive(Deserialize, Debug, PartialEq, Display)]
-pub enum Room {
-    Playroom,
-    Bathroom,
-    Kitchen,
-    LivingRoom,
-}
-
 #[derive(Template)]
 #[template(path = "entry.html")]
 struct EntryTemplate {
@@ -69,6 +77,9 @@ struct EntryTemplate {
     entry_id: String,
     image_url: String,
     rooms: Vec<Room>,
+    tracks: Vec<super::TrackModel>,
+    stats: super::EntryStats,
+    add_to_queue: bool,
 }
 
 pub async fn get_entry(
@@ -77,7 +88,11 @@ pub async fn get_entry(
 ) -> Result<impl IntoResponse, errors::AppError> {
     let category_type = CategoryType::from_str(&category)?;
     let entry = super::get(&state.db, &entry_id).await?;
-    let rooms = state.ha_client.available_rooms().await?;
+    let rooms = state
+        .availability
+        .filter(rooms::list_enabled(&state.db).await?);
+    let tracks = super::list_tracks(&state.db, &entry.id).await?;
+    let stats = super::stats(&state.db, &entry.id).await?;
     Ok(Html(
         EntryTemplate {
             category_id,
@@ -85,6 +100,9 @@ pub async fn get_entry(
             entry_id,
             image_url: entry.image_url,
             rooms,
+            tracks,
+            stats,
+            add_to_queue: state.player.supports_add_to_queue(),
         }
         .render()?,
     ))
@@ -94,13 +112,31 @@ pub async fn get_entry(
 #[template(path = "admin_entries.html")]
 struct ListTemplate {
     categories: Vec<CategoryListModel>,
+    sort: EntrySort,
+    hide_explicit: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    #[serde(default)]
+    sort: EntrySort,
+    #[serde(default)]
+    hide_explicit: bool,
 }
 
 pub async fn admin_list(
+    Query(query): Query<ListQuery>,
     State(state): State<states::AppState>,
 ) -> Result<impl IntoResponse, errors::AppError> {
-    let categories = super::list_all(&state.db).await?;
-    Ok(Html(ListTemplate { categories }.render()?))
+    let categories = super::list_all(&state.db, &query.sort, query.hide_explicit).await?;
+    Ok(Html(
+        ListTemplate {
+            categories,
+            sort: query.sort,
+            hide_explicit: query.hide_explicit,
+        }
+        .render()?,
+    ))
 }
 
 struct CategoryEntryEditModel {
@@ -113,6 +149,7 @@ struct CategoryEntryEditModel {
 struct EditTemplate {
     entry: EntryEditModel,
     categories: Vec<CategoryEntryEditModel>,
+    stats: super::EntryStats,
 }
 
 pub async fn admin_get_entry(
@@ -124,11 +161,19 @@ pub async fn admin_get_entry(
         .await?
         .iter()
         .map(|c| CategoryEntryEditModel {
-            id: c.id.clone(),
+            id: c.id,
             name: c.name.clone(),
         })
         .collect();
-    Ok(Html(EditTemplate { entry, categories }.render()?))
+    let stats = super::stats(&state.db, &entry.id).await?;
+    Ok(Html(
+        EditTemplate {
+            entry,
+            categories,
+            stats,
+        }
+        .render()?,
+    ))
 }
 
 #[serde_as]
@@ -137,41 +182,43 @@ pub struct EntryEditForm {
     id: String,
     name: String,
     image_url: String,
-    entry_type: String,
-    spotify_uri: String,
-    spotify_id: String,
     play_count: i16,
-    blob: serde_json::Value,
 
     #[serde_as(as = "NoneAsEmptyString")]
     category_id: Option<String>,
     #[serde(default)]
     visible: bool,
-    spotify_url: String,
+    #[serde_as(as = "NoneAsEmptyString")]
+    episode_number: Option<i32>,
+    #[serde_as(as = "NoneAsEmptyString")]
+    shuffle: Option<bool>,
+    #[serde_as(as = "NoneAsEmptyString")]
+    repeat: Option<Repeat>,
+    #[serde_as(as = "NoneAsEmptyString")]
+    spoken_name: Option<String>,
 }
 
-impl TryInto<EntryEditModel> for EntryEditForm {
+impl TryInto<EntryUpdateModel> for EntryEditForm {
     type Error = anyhow::Error;
 
-    fn try_into(self) -> anyhow::Result<EntryEditModel, Self::Error> {
+    fn try_into(self) -> anyhow::Result<EntryUpdateModel, Self::Error> {
         let category_id = if let Some(category_id) = self.category_id {
             Some(sqlx::types::Uuid::parse_str(&category_id)?)
         } else {
             Option::None
         };
 
-        Ok(EntryEditModel {
+        Ok(EntryUpdateModel {
             id: sqlx::types::Uuid::parse_str(&self.id)?,
             name: self.name,
             image_url: self.image_url,
-            entry_type: EntryType::from_str(&self.entry_type)?,
-            spotify_uri: self.spotify_uri,
-            spotify_id: self.spotify_id,
             play_count: self.play_count,
-            blob: self.blob,
             category_id,
             visible: self.visible,
-            spotify_url: self.spotify_url,
+            episode_number: self.episode_number,
+            shuffle: self.shuffle,
+            repeat: self.repeat,
+            spoken_name: self.spoken_name,
         })
     }
 }
@@ -179,11 +226,11 @@ pub async fn admin_update(
     State(state): State<states::AppState>,
     Form(entry_form): Form<EntryEditForm>,
 ) -> Result<impl IntoResponse, errors::AppError> {
-    let entry: EntryEditModel = entry_form.try_into()?;
+    let entry: EntryUpdateModel = entry_form.try_into()?;
     super::update(&state.db, &entry).await?;
 
     let mut headers = HeaderMap::new();
-    let path = format!("/admin/entries/{}", entry.id.to_string());
+    let path = format!("/admin/entries/{}", entry.id);
     headers.insert("HX-Redirect", path.parse()?);
     Ok(headers)
 }
@@ -203,14 +250,6 @@ pub struct CreateForm {
     spotify_urls: String,
 }
 
-fn find_image(images: Vec<Image>) -> Result<String> {
-    images
-        .into_iter()
-        .find(with_height)
-        .map(|i| i.url.clone())
-        .ok_or(anyhow::anyhow!("could not extract image url"))
-}
-
 #[derive(Template)]
 #[template(path = "admin_entries_create.html")]
 struct CreateTemplate {}
@@ -226,7 +265,7 @@ pub async fn admin_create(
     let urls: &Vec<Url> = &create_form
         .spotify_urls
         .split('\n')
-        .flat_map(|s| Url::parse(s))
+        .flat_map(Url::parse)
         .collect::<Vec<_>>();
 
     let mut ids: Vec<Uuid> = vec![];
@@ -238,9 +277,11 @@ pub async fn admin_create(
 
     let mut headers = HeaderMap::new();
     if ids.len() == 1 {
-        let id = ids.get(0).ok_or(anyhow::anyhow!("only id did not exist"))?;
+        let id = ids
+            .first()
+            .ok_or(anyhow::anyhow!("only id did not exist"))?;
 
-        let path = format!("/admin/entries/{}", id.to_string());
+        let path = format!("/admin/entries/{}", id);
         headers.insert("HX-Redirect", path.parse()?);
     } else {
         headers.insert("HX-Redirect", "/admin/entries".parse()?);
@@ -253,46 +294,21 @@ async fn create_entry_from_url(
     category_id: Option<sqlx::types::Uuid>,
     spotify: &ClientCredsSpotify,
 ) -> anyhow::Result<EntryCreateModel> {
-    let segments = url
-        .path_segments()
-        .map(|c| c.collect::<Vec<_>>())
-        .ok_or(anyhow::anyhow!("no path available"))?;
-
-    match segments[..] {
-        ["album", id] => {
-            let id = AlbumId::from_id(id)?;
-            let album = spotify.album(id, MARKET).await?;
-            Ok(EntryCreateModel {
-                name: album.name.clone(),
-                image_url: find_image(album.images.clone())?,
-                entry_type: EntryType::Album,
-                spotify_url: url.to_string(),
-                spotify_uri: album.id.uri(),
-                spotify_id: album.id.id().to_string(),
-                play_count: 0,
-                blob: json!(album),
-                visible: false,
-                category_id,
-            })
-        }
-        ["playlist", id] => {
-            let id = PlaylistId::from_id(id)?;
-            let playlist = spotify.playlist(id, None, MARKET).await?;
-            Ok(EntryCreateModel {
-                name: playlist.name.clone(),
-                image_url: find_image(playlist.images.clone())?,
-                entry_type: EntryType::Playlist,
-                spotify_url: url.to_string(),
-                spotify_uri: playlist.id.uri(),
-                spotify_id: playlist.id.id().to_string(),
-                play_count: 0,
-                blob: json!(playlist),
-                visible: false,
-                category_id,
-            })
-        }
-        _ => Err(anyhow::anyhow!("url type not supporeted")),
-    }
+    let (entry_type, id) = source::parse_url(url)?;
+    let source = source::fetch(spotify, &entry_type, &id).await?;
+    Ok(EntryCreateModel {
+        episode_number: super::parse_episode_number(&source.name),
+        name: source.name,
+        image_url: source.image_url,
+        entry_type: source.entry_type,
+        spotify_url: url.to_string(),
+        spotify_uri: source.spotify_uri,
+        spotify_id: source.spotify_id,
+        play_count: 0,
+        blob: source.blob,
+        visible: false,
+        category_id,
+    })
 }
 
 #[derive(Template)]
@@ -310,7 +326,7 @@ pub async fn admin_create_for_category(
     let urls: &Vec<Url> = &create_form
         .spotify_urls
         .split('\n')
-        .flat_map(|s| Url::parse(s))
+        .flat_map(Url::parse)
         .collect::<Vec<_>>();
 
     let mut ids: Vec<Uuid> = vec![];
@@ -323,3 +339,59 @@ pub async fn admin_create_for_category(
     let entries = super::list_all_by_category(&state.db, &category_id.to_string()).await?;
     Ok(Html(PartialEntryList { entries }.render()?))
 }
+
+struct BrokenEntry {
+    entry: super::BrokenEntryModel,
+    suggestions: Vec<source::Suggestion>,
+}
+
+#[derive(Template)]
+#[template(path = "admin_entries_broken.html")]
+struct BrokenTemplate {
+    entries: Vec<BrokenEntry>,
+}
+
+pub async fn admin_broken(
+    State(state): State<states::AppState>,
+) -> Result<impl IntoResponse, errors::AppError> {
+    let mut entries = vec![];
+    for entry in super::list_unavailable(&state.db).await? {
+        // a failed search still lists the entry so that it can be relinked by hand
+        let suggestions = source::suggest(
+            &state.spotify,
+            &entry.name,
+            entry.primary_artist.as_deref(),
+            &entry.spotify_id,
+        )
+        .await
+        .unwrap_or_else(|err| {
+            warn!("could not search replacements for {}: {}", entry.name, err);
+            vec![]
+        });
+        entries.push(BrokenEntry { entry, suggestions });
+    }
+    Ok(Html(BrokenTemplate { entries }.render()?))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RelinkForm {
+    spotify_url: String,
+}
+
+pub async fn admin_relink(
+    Path(entry_id): Path<String>,
+    State(state): State<states::AppState>,
+    Form(relink_form): Form<RelinkForm>,
+) -> Result<impl IntoResponse, errors::AppError> {
+    let id = sqlx::types::Uuid::parse_str(&entry_id)?;
+    let url = Url::parse(relink_form.spotify_url.trim())?;
+    let (entry_type, spotify_id) = source::parse_url(&url)?;
+    let source = source::fetch(&state.spotify, &entry_type, &spotify_id).await?;
+    super::update_source(&state.db, &id, &source).await?;
+    info!("relinked {} to {}", source.name, source.spotify_uri);
+
+    let mut headers = HeaderMap::new();
+    let path = format!("/admin/entries/{}", id);
+    headers.insert("HX-Redirect", path.parse()?);
+    Ok(headers)
+}
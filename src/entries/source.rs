@@ -0,0 +1,168 @@
+use futures_util::TryStreamExt;
+use rspotify::{
+    ClientCredsSpotify, ClientError,
+    http::HttpError,
+    model::{AlbumId, Id, Image, Market, PlaylistId, SearchResult, SearchType},
+    prelude::BaseClient,
+};
+use serde_json::json;
+use url::Url;
+
+use crate::{MARKET, with_height};
+
+use super::EntryType;
+
+/// the spotify album or playlist an entry plays
+#[derive(Debug)]
+pub struct Source {
+    pub entry_type: EntryType,
+    pub name: String,
+    pub image_url: String,
+    pub spotify_uri: String,
+    pub spotify_id: String,
+    pub spotify_url: String,
+    pub blob: serde_json::Value,
+}
+
+fn find_image(images: Vec<Image>) -> anyhow::Result<String> {
+    images
+        .into_iter()
+        .find(with_height)
+        .map(|i| i.url.clone())
+        .ok_or(anyhow::anyhow!("could not extract image url"))
+}
+
+pub fn parse_url(url: &Url) -> anyhow::Result<(EntryType, String)> {
+    let segments = url
+        .path_segments()
+        .map(|c| c.collect::<Vec<_>>())
+        .ok_or(anyhow::anyhow!("no path available"))?;
+
+    match segments[..] {
+        ["album", id] => Ok((EntryType::Album, id.to_string())),
+        ["playlist", id] => Ok((EntryType::Playlist, id.to_string())),
+        _ => Err(anyhow::anyhow!("url type not supporeted")),
+    }
+}
+
+pub async fn fetch(
+    spotify: &ClientCredsSpotify,
+    entry_type: &EntryType,
+    spotify_id: &str,
+) -> anyhow::Result<Source> {
+    match entry_type {
+        EntryType::Album => {
+            let id = AlbumId::from_id(spotify_id)?;
+            let album = spotify.album(id.clone(), MARKET).await?;
+            let tracks = spotify
+                .album_track(id, MARKET)
+                .try_collect::<Vec<_>>()
+                .await?;
+            Ok(Source {
+                entry_type: EntryType::Album,
+                name: album.name.clone(),
+                image_url: find_image(album.images.clone())?,
+                spotify_uri: album.id.uri(),
+                spotify_id: album.id.id().to_string(),
+                spotify_url: format!("https://open.spotify.com/album/{}", album.id.id()),
+                blob: with_tracks(json!(album), json!(tracks)),
+            })
+        }
+        EntryType::Playlist => {
+            let id = PlaylistId::from_id(spotify_id)?;
+            let playlist = spotify.playlist(id.clone(), None, MARKET).await?;
+            let items = spotify
+                .playlist_items(id, None, MARKET)
+                .try_collect::<Vec<_>>()
+                .await?;
+            Ok(Source {
+                entry_type: EntryType::Playlist,
+                name: playlist.name.clone(),
+                image_url: find_image(playlist.images.clone())?,
+                spotify_uri: playlist.id.uri(),
+                spotify_id: playlist.id.id().to_string(),
+                spotify_url: format!("https://open.spotify.com/playlist/{}", playlist.id.id()),
+                blob: with_tracks(json!(playlist), json!(items)),
+            })
+        }
+    }
+}
+
+/// the album and playlist responses only carry the first page of tracks
+fn with_tracks(mut blob: serde_json::Value, items: serde_json::Value) -> serde_json::Value {
+    blob["tracks"] = json!({
+        "total": items.as_array().map_or(0, |items| items.len()),
+        "items": items,
+    });
+    blob
+}
+
+/// spotify answers with 404 for albums and playlists that were pulled
+pub fn is_gone(err: &anyhow::Error) -> bool {
+    match err.downcast_ref::<ClientError>() {
+        Some(ClientError::Http(err)) => matches!(
+            err.as_ref(),
+            HttpError::StatusCode(res) if res.status() == reqwest::StatusCode::NOT_FOUND
+        ),
+        _ => false,
+    }
+}
+
+pub fn market_code() -> Option<&'static str> {
+    match MARKET {
+        Some(Market::Country(country)) => Some(country.into()),
+        _ => None,
+    }
+}
+
+/// an album that could replace a pulled entry
+#[derive(Debug)]
+pub struct Suggestion {
+    pub name: String,
+    pub artists: String,
+    pub image_url: Option<String>,
+    pub release_date: Option<String>,
+    pub spotify_url: String,
+}
+
+/// albums with the same name and artist, re-releases usually come with a new id
+pub async fn suggest(
+    spotify: &ClientCredsSpotify,
+    name: &str,
+    artist: Option<&str>,
+    spotify_id: &str,
+) -> anyhow::Result<Vec<Suggestion>> {
+    let query = match artist {
+        Some(artist) => format!("album:{} artist:{}", name, artist),
+        None => format!("album:{}", name),
+    };
+    let SearchResult::Albums(page) = spotify
+        .search(&query, SearchType::Album, MARKET, None, Some(5), None)
+        .await?
+    else {
+        return Ok(vec![]);
+    };
+
+    Ok(page
+        .items
+        .into_iter()
+        .filter_map(|album| {
+            let id = album.id?;
+            if id.id() == spotify_id {
+                return None;
+            }
+            Some(Suggestion {
+                name: album.name,
+                artists: album
+                    .artists
+                    .iter()
+                    .map(|a| a.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                image_url: album.images.into_iter().find(with_height).map(|i| i.url),
+                release_date: album.release_date,
+                spotify_url: format!("https://open.spotify.com/album/{}", id.id()),
+            })
+        })
+        .collect())
+}
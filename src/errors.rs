@@ -1,8 +1,39 @@
+use askama::Template;
 use axum::http::StatusCode;
 use axum::http::header::InvalidHeaderValue;
-use axum::response::{IntoResponse, Response};
+use axum::response::{Html, IntoResponse, Response};
 use thiserror::Error;
-use tracing::error;
+use tracing::{error, warn};
+
+pub enum MessageKind {
+    QuietHours,
+    SpeakerUnavailable,
+    Unsupported,
+    Takeover,
+    RoomBusy,
+}
+
+/// kid-facing message swapped into the `#message` element of the current page
+#[derive(Template)]
+#[template(path = "partial_message.html")]
+pub struct MessageTemplate<'a> {
+    pub kind: MessageKind,
+    pub icon_url: Option<&'a str>,
+    pub text: &'a str,
+}
+
+impl IntoResponse for MessageTemplate<'_> {
+    fn into_response(self) -> Response {
+        match self.render() {
+            Ok(html) => (
+                [("HX-Retarget", "#message"), ("HX-Reswap", "innerHTML")],
+                Html(html),
+            )
+                .into_response(),
+            Err(err) => AppError::AskamaError(err).into_response(),
+        }
+    }
+}
 
 #[derive(Error, Debug)]
 pub enum AppError {
@@ -44,13 +75,17 @@ pub enum AppError {
 
     #[error("{0}")]
     ToStrError(#[from] reqwest::header::ToStrError),
+
+    #[error("{0}")]
+    Playback(#[from] crate::playback::Error),
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         use AppError::{
-            Anyhow, AskamaError, HeaderError, InternalError, NotFound, RSpotifyClientError,
-            RSpotifyIdError, Sqlx, StrumError, ToStrError, UrlParseError, Utf8Error, UuidError,
+            Anyhow, AskamaError, HeaderError, InternalError, NotFound, Playback,
+            RSpotifyClientError, RSpotifyIdError, Sqlx, StrumError, ToStrError, UrlParseError,
+            Utf8Error, UuidError,
         };
 
         match self {
@@ -100,6 +135,30 @@ impl IntoResponse for AppError {
                 error!("to str error: {}", err);
                 (StatusCode::INTERNAL_SERVER_ERROR).into_response()
             }
+            Playback(err) if err.is_unsupported() => {
+                warn!("playback: {}", err);
+                (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    MessageTemplate {
+                        kind: MessageKind::Unsupported,
+                        icon_url: None,
+                        text: "The speaker cannot do that!",
+                    },
+                )
+                    .into_response()
+            }
+            Playback(err) => {
+                error!("playback: {}", err);
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    MessageTemplate {
+                        kind: MessageKind::SpeakerUnavailable,
+                        icon_url: None,
+                        text: "The speaker is not answering, ask a grown-up!",
+                    },
+                )
+                    .into_response()
+            }
         }
     }
 }
@@ -1,109 +1,336 @@
-use std::collections::HashMap;
+use std::time::Duration;
 
-use axum::http::HeaderMap;
-use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
-use tracing::error;
+use async_trait::async_trait;
+use serde_json::json;
+use tracing::warn;
 
-use crate::entries::{self, handlers::Room};
+use crate::playback::{Backend, Enqueue, Error, PlayerState, Repeat};
+use crate::rooms::Room;
+
+pub mod websocket;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const RETRIES: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+const SPEECH_LEAD: Duration = Duration::from_millis(1500);
+const SPEECH_PER_WORD: Duration = Duration::from_millis(400);
+
+/// a `tts.speak` style service names a tts entity, legacy `tts.*_say` services take the speaker instead
+#[derive(Debug, Clone)]
+struct Tts {
+    service: String,
+    entity_id: Option<String>,
+}
 
 #[derive(Debug, Clone)]
 pub struct Client {
     host: String,
     token: String,
+    http: reqwest::Client,
+    live: websocket::Players,
+    tts: Option<Tts>,
 }
 
 impl Client {
-    pub fn new(host: &str, token: &str) -> Client {
-        Client {
+    pub fn new(host: &str, token: &str) -> Result<Client, Error> {
+        let http = reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            .build()
+            .map_err(|e| Error::Unreachable(e.into()))?;
+        Ok(Client {
             host: host.to_owned(),
             token: token.to_owned(),
-        }
+            http,
+            live: websocket::Players::default(),
+            tts: None,
+        })
     }
 
-    fn entity_id(&self, room: &entries::handlers::Room) -> String {
-        let room = match room {
-            entries::handlers::Room::Playroom => "playroom",
-            entries::handlers::Room::Bathroom => "bathroom",
-            entries::handlers::Room::Kitchen => "kitchen",
-            entries::handlers::Room::LivingRoom => "living_room",
-        };
-        format!("media_player.{}", room)
+    pub fn with_tts(mut self, service: &str, entity_id: Option<&str>) -> Client {
+        self.tts = Some(Tts {
+            service: service.to_owned(),
+            entity_id: entity_id.map(str::to_owned),
+        });
+        self
+    }
+
+    /// the entity picture is served through the home assistant media player proxy
+    fn with_absolute_picture(&self, mut player: PlayerState) -> PlayerState {
+        if let Some(picture) = player
+            .attributes
+            .entity_picture
+            .as_mut()
+            .filter(|p| p.starts_with('/'))
+        {
+            *picture = format!("{}{}", self.host, picture);
+        }
+        player
     }
 
     fn url(&self, service: &str) -> String {
         format!("{}/api/services/media_player/{}", self.host, service)
     }
 
-    fn headers(&self) -> anyhow::Result<HeaderMap> {
-        let mut headers = HeaderMap::new();
-        headers.insert(CONTENT_TYPE, "application/json".parse()?);
-        headers.insert(AUTHORIZATION, format!("Bearer {}", self.token).parse()?);
-        Ok(headers)
+    /// retries connection failures, timeouts and server errors only for reads,
+    /// a command that timed out may still have run and must not be sent twice
+    async fn send(
+        &self,
+        request: impl Fn() -> reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, Error> {
+        let mut attempt = 1;
+        loop {
+            let request = request()
+                .bearer_auth(&self.token)
+                .build()
+                .map_err(|e| Error::Unreachable(e.into()))?;
+            let read = request.method() == reqwest::Method::GET;
+            match self.http.execute(request).await {
+                Ok(res) if res.status() == reqwest::StatusCode::UNAUTHORIZED => {
+                    return Err(Error::Unauthorized);
+                }
+                Ok(res) if res.status().is_server_error() && read && attempt < RETRIES => {
+                    warn!(
+                        "home assistant responded with {} (attempt {})",
+                        res.status(),
+                        attempt
+                    );
+                }
+                Ok(res) => return Ok(res),
+                Err(err)
+                    if (err.is_connect() || (read && err.is_timeout())) && attempt < RETRIES =>
+                {
+                    warn!(
+                        "home assistant is unreachable (attempt {}): {}",
+                        attempt, err
+                    );
+                }
+                Err(err) => return Err(Error::Unreachable(err.into())),
+            }
+            tokio::time::sleep(RETRY_DELAY * attempt).await;
+            attempt += 1;
+        }
     }
 
-    pub async fn play(
-        &self,
-        room: &entries::handlers::Room,
-        spotify_id: &str,
-    ) -> anyhow::Result<()> {
-        let client = reqwest::Client::new();
-        let mut body: HashMap<String, String> = HashMap::new();
-        body.insert("entity_id".to_string(), self.entity_id(room));
-        body.insert("media_content_id".to_string(), spotify_id.to_string());
-        body.insert("media_content_type".to_string(), "playlist".to_string());
-        body.insert("enqueue".to_string(), "replace".to_string());
-
-        let res = client
-            .post(self.url("play_media"))
-            .headers(self.headers()?)
-            .json(&body)
-            .send()
-            .await?;
+    async fn call(&self, service: &str, body: serde_json::Value) -> Result<(), Error> {
+        self.post(self.url(service), body).await
+    }
 
-        if let Err(err) = res.error_for_status() {
-            error!("could not send request to home assist: {}", err)
+    async fn post(&self, url: String, body: serde_json::Value) -> Result<(), Error> {
+        let res = self.send(|| self.http.post(&url).json(&body)).await?;
+
+        if !res.status().is_success() {
+            return Err(Error::Status(res.status()));
         }
         Ok(())
     }
 
-    pub async fn available_rooms(&self) -> anyhow::Result<Vec<Room>> {
-        let client = reqwest::Client::new();
-        let res = client
-            .get(format!("{}/api/states", self.host))
-            .headers(self.headers()?)
-            .send()
-            .await?;
+    async fn ensure_available(&self, room: &Room) -> Result<(), Error> {
+        match self.player(room).await? {
+            Some(player) if player.is_available() => Ok(()),
+            _ => Err(Error::EntityUnavailable(room.entity_id.clone())),
+        }
+    }
+}
+
+#[async_trait]
+impl Backend for Client {
+    async fn play(&self, room: &Room, spotify_uri: &str) -> Result<(), Error> {
+        self.ensure_available(room).await?;
+        self.volume_set(room, room.default_volume).await?;
+        self.call(
+            "play_media",
+            json!({
+                "entity_id": room.entity_id,
+                "media_content_id": spotify_uri,
+                "media_content_type": "playlist",
+                "enqueue": "replace",
+            }),
+        )
+        .await
+    }
+
+    async fn enqueue(&self, room: &Room, spotify_uri: &str, enqueue: Enqueue) -> Result<(), Error> {
+        self.ensure_available(room).await?;
+        self.call(
+            "play_media",
+            json!({
+                "entity_id": room.entity_id,
+                "media_content_id": spotify_uri,
+                "media_content_type": "playlist",
+                "enqueue": enqueue.as_ref(),
+            }),
+        )
+        .await
+    }
+
+    async fn pause(&self, room: &Room) -> Result<(), Error> {
+        self.call("media_pause", json!({ "entity_id": room.entity_id }))
+            .await
+    }
+
+    async fn resume(&self, room: &Room) -> Result<(), Error> {
+        self.call("media_play", json!({ "entity_id": room.entity_id }))
+            .await
+    }
+
+    async fn stop(&self, room: &Room) -> Result<(), Error> {
+        self.call("media_stop", json!({ "entity_id": room.entity_id }))
+            .await
+    }
+
+    async fn next(&self, room: &Room) -> Result<(), Error> {
+        self.call("media_next_track", json!({ "entity_id": room.entity_id }))
+            .await
+    }
+
+    async fn previous(&self, room: &Room) -> Result<(), Error> {
+        self.call(
+            "media_previous_track",
+            json!({ "entity_id": room.entity_id }),
+        )
+        .await
+    }
 
-        let body = res.json::<serde_json::Value>().await?;
-
-        return if let Some(room_ids) = body.as_array().map(|a| {
-            a.into_iter()
-                .filter(|&e| {
-                    e["entity_id"]
-                        .as_str()
-                        .is_some_and(|e| e.starts_with("media_player"))
-                        && e["state"].as_str().is_some_and(|e| e != "unavailable")
-                })
-                .map(|e| {
-                    e["entity_id"]
-                        .as_str()
-                        .map_or("", |e| e.trim_start_matches("media_player."))
-                })
-                .map(|e| match e {
-                    "playroom" => entries::handlers::Room::Playroom,
-                    "kitchen" => entries::handlers::Room::Kitchen,
-                    "living_room" => entries::handlers::Room::LivingRoom,
-                    "bathroom" => entries::handlers::Room::Bathroom,
-                    _ => {
-                        error!("could not match {} to room enum", e);
-                        entries::handlers::Room::Playroom
-                    }
-                })
-                .collect::<Vec<_>>()
-        }) {
-            Ok(room_ids)
-        } else {
-            Ok(vec![])
+    async fn volume_set(&self, room: &Room, volume: f32) -> Result<(), Error> {
+        self.call(
+            "volume_set",
+            json!({ "entity_id": room.entity_id, "volume_level": room.clamp_volume(volume) }),
+        )
+        .await
+    }
+
+    async fn join(&self, leader: &Room, members: &[Room]) -> Result<(), Error> {
+        self.ensure_available(leader).await?;
+        for member in members {
+            self.ensure_available(member).await?;
+            self.volume_set(member, member.default_volume).await?;
+        }
+        self.call(
+            "join",
+            json!({
+                "entity_id": leader.entity_id,
+                "group_members": members.iter().map(|m| &m.entity_id).collect::<Vec<_>>(),
+            }),
+        )
+        .await
+    }
+
+    /// speakers without grouping support reject the call, so it is only sent to grouped players
+    async fn unjoin(&self, room: &Room) -> Result<(), Error> {
+        match self.player(room).await? {
+            Some(player) if !player.is_available() => {
+                Err(Error::EntityUnavailable(room.entity_id.clone()))
+            }
+            Some(player) if player.is_grouped() => {
+                self.call("unjoin", json!({ "entity_id": room.entity_id }))
+                    .await
+            }
+            Some(_) => Ok(()),
+            None => Err(Error::EntityUnavailable(room.entity_id.clone())),
+        }
+    }
+
+    async fn seek(&self, room: &Room, position: f64) -> Result<(), Error> {
+        self.call(
+            "media_seek",
+            json!({ "entity_id": room.entity_id, "seek_position": position }),
+        )
+        .await
+    }
+
+    async fn shuffle_set(&self, room: &Room, shuffle: bool) -> Result<(), Error> {
+        self.call(
+            "shuffle_set",
+            json!({ "entity_id": room.entity_id, "shuffle": shuffle }),
+        )
+        .await
+    }
+
+    async fn repeat_set(&self, room: &Room, repeat: Repeat) -> Result<(), Error> {
+        self.call(
+            "repeat_set",
+            json!({ "entity_id": room.entity_id, "repeat": repeat.as_ref() }),
+        )
+        .await
+    }
+
+    async fn announce(&self, room: &Room, message: &str) -> Result<(), Error> {
+        let Some(tts) = &self.tts else {
+            return Err(Error::Unsupported("announcements".to_string()));
+        };
+        self.ensure_available(room).await?;
+        let body = match &tts.entity_id {
+            Some(entity_id) => json!({
+                "entity_id": entity_id,
+                "media_player_entity_id": room.entity_id,
+                "message": message,
+            }),
+            None => json!({ "entity_id": room.entity_id, "message": message }),
         };
+        let url = format!(
+            "{}/api/services/{}",
+            self.host,
+            tts.service.replacen('.', "/", 1)
+        );
+        self.post(url, body).await?;
+
+        // the service returns as soon as the speaker starts talking, playback would cut it off
+        let words = message.split_whitespace().count() as u32;
+        tokio::time::sleep(SPEECH_LEAD + SPEECH_PER_WORD * words).await;
+        Ok(())
+    }
+
+    async fn players(&self) -> Result<Vec<PlayerState>, Error> {
+        if let Some(players) = self.live.all() {
+            return Ok(players);
+        }
+
+        let res = self
+            .send(|| self.http.get(format!("{}/api/states", self.host)))
+            .await?;
+        if !res.status().is_success() {
+            return Err(Error::Status(res.status()));
+        }
+
+        let body = res
+            .json::<Vec<serde_json::Value>>()
+            .await
+            .map_err(|e| Error::InvalidResponse(e.into()))?;
+
+        Ok(body
+            .into_iter()
+            .filter(|e| {
+                e["entity_id"]
+                    .as_str()
+                    .is_some_and(|e| e.starts_with("media_player."))
+            })
+            .flat_map(serde_json::from_value::<PlayerState>)
+            .map(|p| self.with_absolute_picture(p))
+            .collect())
+    }
+
+    async fn player(&self, room: &Room) -> Result<Option<PlayerState>, Error> {
+        if let Some(player) = self.live.get(&room.entity_id) {
+            return Ok(player);
+        }
+
+        let res = self
+            .send(|| {
+                self.http
+                    .get(format!("{}/api/states/{}", self.host, room.entity_id))
+            })
+            .await?;
+
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !res.status().is_success() {
+            return Err(Error::Status(res.status()));
+        }
+        let player = res
+            .json::<PlayerState>()
+            .await
+            .map_err(|e| Error::InvalidResponse(e.into()))?;
+        Ok(Some(self.with_absolute_picture(player)))
     }
 }
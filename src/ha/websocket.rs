@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::{Context, anyhow};
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::json;
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{error, info, warn};
+
+use crate::playback::PlayerState;
+
+use super::Client;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+/// a quiet connection is pinged after this long and dropped if the pong takes as long again
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// live state of every media player, `None` while the websocket is not connected
+#[derive(Debug, Clone, Default)]
+pub struct Players(Arc<RwLock<Option<HashMap<String, PlayerState>>>>);
+
+impl Players {
+    pub fn all(&self) -> Option<Vec<PlayerState>> {
+        let players = self.0.read().unwrap_or_else(|e| e.into_inner());
+        players.as_ref().map(|p| p.values().cloned().collect())
+    }
+
+    /// the outer `None` means the live state is unknown, the inner one that the player does not exist
+    pub fn get(&self, entity_id: &str) -> Option<Option<PlayerState>> {
+        let players = self.0.read().unwrap_or_else(|e| e.into_inner());
+        players.as_ref().map(|p| p.get(entity_id).cloned())
+    }
+
+    fn connected(&self, states: HashMap<String, PlayerState>) {
+        *self.0.write().unwrap_or_else(|e| e.into_inner()) = Some(states);
+    }
+
+    fn disconnected(&self) {
+        *self.0.write().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+
+    fn update(&self, entity_id: String, state: Option<PlayerState>) {
+        let mut players = self.0.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(players) = players.as_mut() {
+            match state {
+                Some(state) => players.insert(entity_id, state),
+                None => players.remove(&entity_id),
+            };
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Incoming {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    success: Option<bool>,
+    #[serde(default)]
+    result: serde_json::Value,
+    #[serde(default)]
+    event: Option<Event>,
+}
+
+/// events of every domain arrive here, only media players are read into a typed state
+#[derive(Deserialize)]
+struct Event {
+    #[serde(default)]
+    data: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct EventData {
+    entity_id: String,
+    new_state: Option<PlayerState>,
+}
+
+fn is_media_player(entity_id: &str) -> bool {
+    entity_id.starts_with("media_player.")
+}
+
+async fn listen(client: &Client) -> anyhow::Result<()> {
+    let url = format!("{}/api/websocket", client.host.replacen("http", "ws", 1));
+    let (mut socket, _) = connect_async(&url)
+        .await
+        .context("could not connect to the home assistant websocket")?;
+
+    let mut id = 0;
+    let mut ping_sent = false;
+    loop {
+        let message = match tokio::time::timeout(PING_INTERVAL, socket.next()).await {
+            Ok(Some(message)) => message?,
+            Ok(None) => break,
+            Err(_) if ping_sent || id == 0 => {
+                return Err(anyhow!("home assistant websocket stopped answering"));
+            }
+            Err(_) => {
+                id += 1;
+                let ping = json!({ "id": id, "type": "ping" });
+                socket.send(Message::Text(ping.to_string().into())).await?;
+                ping_sent = true;
+                continue;
+            }
+        };
+        let Message::Text(text) = message else {
+            continue;
+        };
+        let incoming: Incoming = serde_json::from_str(text.as_str())?;
+        match incoming.kind.as_str() {
+            "auth_required" => {
+                let auth = json!({ "type": "auth", "access_token": client.token });
+                socket.send(Message::Text(auth.to_string().into())).await?;
+            }
+            "auth_invalid" => return Err(anyhow!("home assistant rejected the access token")),
+            "auth_ok" => {
+                id += 1;
+                let get_states = json!({ "id": id, "type": "get_states" });
+                socket
+                    .send(Message::Text(get_states.to_string().into()))
+                    .await?;
+                id += 1;
+                let subscribe = json!({
+                    "id": id,
+                    "type": "subscribe_events",
+                    "event_type": "state_changed",
+                });
+                socket
+                    .send(Message::Text(subscribe.to_string().into()))
+                    .await?;
+            }
+            "result" if incoming.success == Some(false) => {
+                return Err(anyhow!(
+                    "home assistant websocket error: {}",
+                    incoming.result
+                ));
+            }
+            "result" if incoming.result.is_array() => {
+                let states = serde_json::from_value::<Vec<serde_json::Value>>(incoming.result)?
+                    .into_iter()
+                    .flat_map(serde_json::from_value::<PlayerState>)
+                    .filter(|p| is_media_player(&p.entity_id))
+                    .map(|p| (p.entity_id.clone(), client.with_absolute_picture(p)))
+                    .collect();
+                client.live.connected(states);
+                info!("subscribed to home assistant player events");
+            }
+            "event" => {
+                let Some(event) = incoming.event else {
+                    continue;
+                };
+                if !event.data["entity_id"]
+                    .as_str()
+                    .is_some_and(is_media_player)
+                {
+                    continue;
+                }
+                match serde_json::from_value::<EventData>(event.data) {
+                    Ok(data) => client.live.update(
+                        data.entity_id,
+                        data.new_state.map(|p| client.with_absolute_picture(p)),
+                    ),
+                    Err(err) => warn!("skipped an unreadable player event: {}", err),
+                }
+            }
+            "pong" => ping_sent = false,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// keeps the live player model in sync, the rest api is used while the connection is down
+pub async fn run(client: Client) {
+    loop {
+        if let Err(err) = listen(&client).await {
+            error!("home assistant websocket: {}", err);
+        }
+        client.live.disconnected();
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
@@ -878,6 +878,7 @@ pub async fn import_all(db: &PgPool, spotify: &ClientCredsSpotify) -> anyhow::Re
                 }
                 let album = spotify.album(id, MARKET).await?;
                 Ok(EntryCreateModel {
+                    episode_number: entries::parse_episode_number(&album.name),
                     name: album.name.clone(),
                     image_url: find_image(album.images.clone())?,
                     entry_type: EntryType::Album,
@@ -1,33 +1,39 @@
 use anyhow::{Context, anyhow};
 use askama::Template;
 use axum::extract::{Path, State};
-use axum::response::{Html, IntoResponse};
-use axum_extra::extract::Query;
+use axum::response::{Html, IntoResponse, Response};
+use axum_extra::extract::{Form, Query};
 use categories::{Category, CategoryType};
 use entries::{CategoryListModel, EntryListModel};
 use errors::AppError;
+use futures_util::future::join_all;
 use rspotify::model::{AlbumId, Image, Market, PlaylistId};
 use rspotify::prelude::BaseClient;
 use rspotify::{ClientCredsSpotify, Credentials};
 use serde::Deserialize;
 use sqlx::postgres::PgPoolOptions;
 
+use axum::Router;
 use axum::http::{HeaderMap, StatusCode};
-use axum::routing::{get, post};
-use axum::{Form, Router};
+use axum::routing::{delete, get, post};
 use tower_http::services::{ServeDir, ServeFile};
 use url::Url;
 
 use std::net::SocketAddr;
 use std::str::FromStr;
+use std::sync::Arc;
 
-use tracing::info;
+use tracing::{info, warn};
 
 pub mod categories;
 pub mod entries;
 pub mod errors;
 pub mod ha;
 pub mod import;
+pub mod playback;
+pub mod player;
+pub mod rooms;
+pub mod sleep_timers;
 pub mod states;
 
 // TODO:
@@ -42,8 +48,8 @@ async fn main() -> anyhow::Result<()> {
     let database_url =
         dotenvy::var("DATABASE_URL").context("no postgres connection url provided")?;
 
-    let ha_host = dotenvy::var("HA_HOST").context("no home assistent connection url provided")?;
-    let ha_token = dotenvy::var("HA_TOKEN").context("no home assistent token provided")?;
+    let timezone = dotenvy::var("TIMEZONE").unwrap_or_else(|_| "Europe/Berlin".to_string());
+    let hide_explicit = dotenvy::var("HIDE_EXPLICIT").map_or_else(|_| Ok(false), |h| h.parse())?;
 
     let creds = Credentials::from_env().context("no spotify credentials found.")?;
 
@@ -58,15 +64,53 @@ async fn main() -> anyhow::Result<()> {
         .await
         .context("could not connect to database")?;
     sqlx::migrate!().run(&db).await?;
+    rooms::check_timezone(&db, &timezone)
+        .await
+        .with_context(|| format!("unknown TIMEZONE {}", timezone))?;
+
+    let spotify_auth = playback::spotify::client();
 
-    let ha_client = ha::Client::new(&ha_host, &ha_token);
+    let player: Arc<dyn playback::Backend> = match dotenvy::var("PLAYBACK_BACKEND").as_deref() {
+        Ok("simulated") => {
+            info!("using the simulated player");
+            Arc::new(playback::simulated::Player::default())
+        }
+        Ok("spotify") => {
+            let client = spotify_auth
+                .clone()
+                .context("no spotify redirect uri provided")?;
+            Arc::new(playback::spotify::Player::new(db.clone(), client))
+        }
+        Ok("home-assistant") | Err(_) => {
+            let ha_host =
+                dotenvy::var("HA_HOST").context("no home assistent connection url provided")?;
+            let ha_token = dotenvy::var("HA_TOKEN").context("no home assistent token provided")?;
+            let mut client = ha::Client::new(&ha_host, &ha_token)?;
+            if let Ok(service) = dotenvy::var("HA_TTS_SERVICE") {
+                let entity_id = dotenvy::var("HA_TTS_ENTITY").ok();
+                client = client.with_tts(&service, entity_id.as_deref());
+            }
+            tokio::spawn(ha::websocket::run(client.clone()));
+            Arc::new(client)
+        }
+        Ok(backend) => return Err(anyhow!("unknown playback backend {}", backend)),
+    };
 
     let state = states::AppState {
         db,
         spotify,
-        ha_client,
+        spotify_auth,
+        player,
+        availability: playback::Availability::default(),
+        timezone,
+        hide_explicit,
     };
 
+    tokio::spawn(player::track_availability(state.clone()));
+    tokio::spawn(player::track_positions(state.clone()));
+    tokio::spawn(entries::refresh_metadata(state.clone()));
+    tokio::spawn(sleep_timers::run(state.clone()));
+
     let app = Router::new()
         .route("/{category}/categories", get(categories::handlers::list))
         .route(
@@ -77,6 +121,13 @@ async fn main() -> anyhow::Result<()> {
             "/{category}/categories/{category_id}/entries/{entry_id}",
             get(entries::handlers::get_entry).post(play),
         )
+        .route(
+            "/{category}/categories/{category_id}/entries/{entry_id}/queue/{position}",
+            post(enqueue),
+        )
+        .route("/now-playing", get(player::handlers::now_playing))
+        .route("/player/{command}", post(player::handlers::control))
+        .route("/sleep-timers", post(sleep_timers::handlers::set))
         .route("/admin", get(admin_index))
         .route("/search", get(admin_search))
         // .route("/admin/import", get(admin_import))
@@ -96,6 +147,14 @@ async fn main() -> anyhow::Result<()> {
             post(entries::handlers::admin_create_for_category),
         )
         .route("/admin/entries", get(entries::handlers::admin_list))
+        .route(
+            "/admin/entries/broken",
+            get(entries::handlers::admin_broken),
+        )
+        .route(
+            "/admin/entries/{entry_id}/relink",
+            post(entries::handlers::admin_relink),
+        )
         .route(
             "/admin/entries/new",
             get(entries::handlers::admin_new).post(entries::handlers::admin_create),
@@ -106,6 +165,38 @@ async fn main() -> anyhow::Result<()> {
                 .put(entries::handlers::admin_update)
                 .delete(entries::handlers::admin_delete),
         )
+        .route("/admin/rooms", get(rooms::handlers::admin_list))
+        .route(
+            "/admin/rooms/new",
+            get(rooms::handlers::admin_new).post(rooms::handlers::admin_create),
+        )
+        .route(
+            "/admin/rooms/{room_id}",
+            get(rooms::handlers::admin_get_room)
+                .put(rooms::handlers::admin_update)
+                .delete(rooms::handlers::admin_delete),
+        )
+        .route(
+            "/admin/rooms/{room_id}/schedules",
+            post(rooms::handlers::admin_create_schedule),
+        )
+        .route(
+            "/admin/rooms/{room_id}/schedules/{schedule_id}",
+            delete(rooms::handlers::admin_delete_schedule),
+        )
+        .route(
+            "/admin/spotify/login",
+            get(playback::spotify::handlers::login),
+        )
+        .route(
+            "/admin/spotify/callback",
+            get(playback::spotify::handlers::callback),
+        )
+        .route("/admin/remote", get(player::handlers::admin_remote))
+        .route(
+            "/admin/remote/stop-all",
+            post(player::handlers::admin_stop_all),
+        )
         .route("/admin/image-selection", post(admin_image_selection))
         .route("/", get(index))
         .route("/health", get(health))
@@ -132,7 +223,7 @@ async fn health() -> (StatusCode, impl IntoResponse) {
 }
 
 async fn index(state: State<states::AppState>) -> Result<impl IntoResponse, errors::AppError> {
-    return categories::handlers::list(Path(String::from("audiobook")), state).await;
+    categories::handlers::list(Path(String::from("audiobook")), state).await
 }
 
 #[derive(Template)]
@@ -216,7 +307,7 @@ async fn admin_search(
         .map(|c| c.collect::<Vec<_>>())
         .ok_or(anyhow!("no path segments on url"))?;
 
-    return match segments[..] {
+    match segments[..] {
         ["admin", "entries"] => {
             info!("search for admin entries");
             let categories = entries::admin_search(&state.db, &search.query).await?;
@@ -264,7 +355,8 @@ async fn admin_search(
                 category_type, category_id
             );
             let category_type = CategoryType::from_str(category_type)?;
-            let entries = entries::search(&state.db, category_id, &search.query).await?;
+            let entries =
+                entries::search(&state.db, category_id, &search.query, state.hide_explicit).await?;
             Ok(Html(
                 EntrySearchResultBlock {
                     category_type,
@@ -275,7 +367,7 @@ async fn admin_search(
             ))
         }
         _ => Err(AppError::Anyhow(anyhow!("search not supported"))),
-    };
+    }
 }
 
 // async fn admin_import(
@@ -352,23 +444,589 @@ pub async fn admin_image_selection(
 
 #[derive(Deserialize, Debug)]
 pub struct RoomSelectionForm {
-    room: entries::handlers::Room,
+    #[serde(default)]
+    room: Vec<String>,
+    #[serde(default)]
+    from_start: bool,
+    #[serde(default)]
+    sleep_minutes: Option<i32>,
+    #[serde(default)]
+    track_number: Option<i32>,
+    #[serde(default)]
+    takeover: bool,
+}
+/// kid-facing refusal when one of the rooms is inside its quiet hours
+async fn refuse_quiet_hours(
+    state: &states::AppState,
+    entry_name: &str,
+    selected_rooms: &[rooms::Room],
+) -> Result<Option<Response>, errors::AppError> {
+    for room in selected_rooms {
+        if !rooms::is_playback_allowed(&state.db, &room.id, &state.timezone).await? {
+            info!("refused {} in {}: quiet hours", entry_name, &room.name);
+            return Ok(Some(
+                errors::MessageTemplate {
+                    kind: errors::MessageKind::QuietHours,
+                    icon_url: Some(&room.icon_url),
+                    text: "Sleeping time, try again later!",
+                }
+                .into_response(),
+            ));
+        }
+    }
+    Ok(None)
+}
+
+/// how long an entry started from the app counts as someone else's
+const TAKEOVER_MINUTES: i32 = 15;
+
+/// asks before replacing what another child started in one of the rooms, or refuses when the room forbids it
+async fn refuse_takeover(
+    state: &states::AppState,
+    entry: &entries::EntryEditModel,
+    selected_rooms: &[rooms::Room],
+    confirmed: bool,
+) -> Result<Option<Response>, errors::AppError> {
+    for room in selected_rooms {
+        let Some(recent) = rooms::recent_play(&state.db, &room.id, TAKEOVER_MINUTES).await? else {
+            continue;
+        };
+        if recent == entry.id {
+            continue;
+        }
+        let Some(player) = state.player.player(room).await? else {
+            continue;
+        };
+        if player.state != "playing" {
+            continue;
+        }
+        let playing = match &player.attributes.media_content_id {
+            Some(media_content_id) => {
+                entries::find_by_media_content_id(&state.db, media_content_id).await?
+            }
+            None => None,
+        };
+        if playing.is_none_or(|p| p.id != recent.to_string()) {
+            continue;
+        }
+
+        if !room.allow_takeover {
+            info!("refused {} in {}: room is busy", &entry.name, &room.name);
+            return Ok(Some(
+                errors::MessageTemplate {
+                    kind: errors::MessageKind::RoomBusy,
+                    icon_url: Some(&room.icon_url),
+                    text: "Someone is listening here, pick another room!",
+                }
+                .into_response(),
+            ));
+        }
+        if !confirmed {
+            info!(
+                "asking before replacing the entry playing in {}",
+                &room.name
+            );
+            return Ok(Some(
+                errors::MessageTemplate {
+                    kind: errors::MessageKind::Takeover,
+                    icon_url: Some(&room.icon_url),
+                    text: "Someone is listening here, play anyway?",
+                }
+                .into_response(),
+            ));
+        }
+    }
+    Ok(None)
 }
+
+/// an announcement that does not come through must not keep the children from listening
+async fn announce(
+    state: &states::AppState,
+    entry: &entries::EntryEditModel,
+    selected_rooms: &[rooms::Room],
+) {
+    let announcements = selected_rooms.iter().filter(|r| r.announce).map(|room| {
+        let message = format!("Now playing: {} in the {}", entry.spoken_name(), room.name);
+        // the announcement must not be louder than the playback that follows it
+        async move {
+            let announced = async {
+                state.player.volume_set(room, room.default_volume).await?;
+                state.player.announce(room, &message).await
+            }
+            .await;
+            if let Err(err) = announced {
+                warn!(
+                    "could not announce {} in {}: {}",
+                    &entry.name, &room.name, err
+                );
+            }
+        }
+    });
+    join_all(announcements).await;
+}
+
 pub async fn play(
-    Path((category, category_id, entry_id)): Path<(String, String, String)>,
+    Path((_category, _category_id, entry_id)): Path<(String, String, String)>,
     State(state): State<states::AppState>,
     Form(room_selection_form): Form<RoomSelectionForm>,
-) -> Result<impl IntoResponse, errors::AppError> {
+) -> Result<Response, errors::AppError> {
     let entry = entries::get(&state.db, &entry_id).await?;
+    let selected_rooms = rooms::list_by_ids(&state.db, &room_selection_form.room).await?;
+    let (room, members) = selected_rooms
+        .split_first()
+        .ok_or(anyhow!("no room selected"))?;
+    if let Some(refusal) = refuse_quiet_hours(&state, &entry.name, &selected_rooms).await? {
+        return Ok(refusal);
+    }
+    if let Some(refusal) = refuse_takeover(
+        &state,
+        &entry,
+        &selected_rooms,
+        room_selection_form.takeover,
+    )
+    .await?
+    {
+        return Ok(refusal);
+    }
+    if members.is_empty() {
+        state.player.unjoin(room).await?;
+    } else {
+        state.player.join(room, members).await?;
+    }
+    announce(&state, &entry, &selected_rooms).await;
+
+    let position = if let Some(track_number) = room_selection_form.track_number {
+        Some(player::PlaybackPosition {
+            track_number,
+            position: 0.0,
+        })
+    } else if room_selection_form.from_start {
+        player::delete_position(&state.db, &entry.id, &room.id).await?;
+        None
+    } else {
+        player::get_position(&state.db, &entry.id, &room.id).await?
+    };
+    let started = match position {
+        Some(position) => {
+            info!(
+                "playing {} from track {} ({}s)",
+                &entry.name, position.track_number, position.position
+            );
+            state
+                .player
+                .play_from(
+                    room,
+                    &entry.spotify_uri,
+                    position.track_number,
+                    position.position,
+                )
+                .await
+        }
+        None => state.player.play(room, &entry.spotify_uri).await,
+    };
+    // the entry is playing by then, it only starts earlier than where it was left
+    let started = match started {
+        Err(err @ playback::Error::Timeout(_)) => {
+            warn!("{} did not resume in {}: {}", &entry.name, room.name, err);
+            Ok(())
+        }
+        started => started,
+    };
+    // the speaker may just be asleep, so only spotify itself can tell whether the entry was pulled
+    if started.is_err() {
+        entries::check_source(&state, &entry).await;
+    }
+    started?;
+    // the entry is already playing, a speaker without shuffle or repeat must not turn that into an error
+    let modes = entries::playback_modes(&state.db, &entry.id).await?;
+    for mode in [
+        state.player.shuffle_set(room, modes.shuffle).await,
+        state.player.repeat_set(room, modes.repeat).await,
+    ] {
+        match mode {
+            Err(err) if err.is_unsupported() => {
+                warn!("could not set playback modes in {}: {}", room.name, err)
+            }
+            mode => mode?,
+        }
+    }
+
+    if let Some(minutes) = room_selection_form.sleep_minutes {
+        for room in &selected_rooms {
+            sleep_timers::set(&state.db, &room.id, minutes).await?;
+        }
+    }
+
+    for room in &selected_rooms {
+        rooms::record_play(&state.db, &room.id, &entry.id).await?;
+        entries::clear_queued(&state.db, &room.id).await?;
+    }
+    entries::increment_play_count(&state.db, &entry_id).await?;
+    if let Some(category_id) = &entry.category_id {
+        categories::set_last_entry(&state.db, category_id, &entry.id, &room.id).await?;
+    }
+    info!(
+        "started {} in {}",
+        &entry.name,
+        selected_rooms
+            .iter()
+            .map(|r| r.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    let mut headers = HeaderMap::new();
+    headers.insert("HX-Redirect", "/now-playing".parse()?);
+    Ok(headers.into_response())
+}
+
+pub async fn enqueue(
+    Path((_category, _category_id, entry_id, position)): Path<(String, String, String, String)>,
+    State(state): State<states::AppState>,
+    Form(room_selection_form): Form<RoomSelectionForm>,
+) -> Result<Response, errors::AppError> {
+    let enqueue = playback::Enqueue::from_str(&position)?;
+    let entry = entries::get(&state.db, &entry_id).await?;
+    let selected_rooms = rooms::list_by_ids(&state.db, &room_selection_form.room).await?;
+    // grouped rooms share the queue of the first one
+    let room = selected_rooms.first().ok_or(anyhow!("no room selected"))?;
+    if let Some(refusal) = refuse_quiet_hours(&state, &entry.name, &selected_rooms).await? {
+        return Ok(refusal);
+    }
+
     state
-        .ha_client
-        .play(&room_selection_form.room, &entry.spotify_uri)
+        .player
+        .enqueue(room, &entry.spotify_uri, enqueue)
         .await?;
+    entries::queue(&state.db, &room.id, &entry.id).await?;
 
-    entries::increment_play_count(&state.db, &entry_id).await?;
-    info!("started {} in {}", &entry.name, &room_selection_form.room);
+    info!("queued {} ({}) in {}", &entry.name, enqueue, &room.name);
     let mut headers = HeaderMap::new();
-    let path = format!("/{}/categories/{}/entries", category, category_id);
-    headers.insert("HX-Redirect", path.parse()?);
-    Ok(headers)
+    headers.insert("HX-Redirect", "/now-playing".parse()?);
+    Ok(headers.into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::body::{Body, to_bytes};
+    use axum::extract::{FromRequest, Path, Request, State};
+    use axum::http::header::CONTENT_TYPE;
+    use axum::response::Response;
+    use axum_extra::extract::Form;
+    use rspotify::{ClientCredsSpotify, Credentials};
+    use serde_json::json;
+    use sqlx::PgPool;
+    use sqlx::types::Uuid;
+
+    use crate::playback::{self, simulated};
+    use crate::{RoomSelectionForm, entries, errors, play, player, rooms, states};
+
+    fn state(db: PgPool) -> (states::AppState, Arc<simulated::Player>) {
+        let player = Arc::new(simulated::Player::default());
+        let state = states::AppState {
+            db,
+            spotify: ClientCredsSpotify::new(Credentials::new("id", "secret")),
+            spotify_auth: None,
+            player: player.clone(),
+            availability: playback::Availability::default(),
+            timezone: "UTC".to_string(),
+            hide_explicit: false,
+        };
+        (state, player)
+    }
+
+    async fn entry(db: &PgPool, spotify_id: &str) -> Uuid {
+        sqlx::query_scalar(
+            r#"
+            INSERT INTO entries (name, image_url, entry_type, spotify_uri, spotify_id, play_count, blob, visible, spotify_url)
+            VALUES ($1, '', 'album', 'spotify:album:' || $1, $1, 0, '{}', TRUE, '')
+            RETURNING id
+            "#,
+        )
+        .bind(spotify_id)
+        .fetch_one(db)
+        .await
+        .unwrap()
+    }
+
+    /// the rooms migration seeds a kitchen
+    async fn kitchen(db: &PgPool) -> Uuid {
+        sqlx::query_scalar("SELECT id FROM rooms WHERE entity_id = 'media_player.kitchen'")
+            .fetch_one(db)
+            .await
+            .unwrap()
+    }
+
+    async fn play_in(
+        state: &states::AppState,
+        entry_id: &Uuid,
+        room_id: &Uuid,
+        takeover: bool,
+    ) -> Response {
+        play(
+            Path((
+                "audiobook".to_string(),
+                Uuid::nil().to_string(),
+                entry_id.to_string(),
+            )),
+            State(state.clone()),
+            Form(RoomSelectionForm {
+                room: vec![room_id.to_string()],
+                from_start: false,
+                sleep_minutes: None,
+                track_number: None,
+                takeover,
+            }),
+        )
+        .await
+        .unwrap()
+    }
+
+    /// posts the edit form the way the admin page sends it
+    async fn save(state: &states::AppState, entry_id: &Uuid) {
+        let request = Request::post(format!("/admin/entries/{entry_id}"))
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(format!(
+                "id={entry_id}&name=Renamed&image_url=&play_count=3&category_id=&visible=true&episode_number=&shuffle=&repeat=&spoken_name="
+            )))
+            .unwrap();
+        let form = axum::Form::from_request(request, &()).await.unwrap();
+        entries::handlers::admin_update(State(state.clone()), form)
+            .await
+            .unwrap();
+    }
+
+    async fn body(response: Response) -> String {
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[sqlx::test]
+    async fn play_starts_the_entry_in_the_room(db: PgPool) {
+        let (state, player) = state(db.clone());
+        let entry_id = entry(&db, "first").await;
+        let room_id = kitchen(&db).await;
+
+        let response = play_in(&state, &entry_id, &room_id, false).await;
+
+        assert_eq!(response.headers()["HX-Redirect"], "/now-playing");
+        assert!(
+            player
+                .commands()
+                .contains(&"media_player.kitchen: play spotify:album:first".to_string())
+        );
+        let play_count: Option<i16> =
+            sqlx::query_scalar("SELECT play_count FROM entries WHERE id = $1")
+                .bind(entry_id)
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(play_count, Some(1));
+    }
+
+    #[sqlx::test]
+    async fn play_is_refused_during_quiet_hours(db: PgPool) {
+        let (state, player) = state(db.clone());
+        let entry_id = entry(&db, "first").await;
+        let room_id = kitchen(&db).await;
+        sqlx::query(
+            r#"
+            INSERT INTO room_schedules (room_id, starts_at, ends_at)
+            VALUES (
+                $1,
+                (NOW() AT TIME ZONE 'UTC' + INTERVAL '1 hour')::time,
+                (NOW() AT TIME ZONE 'UTC' + INTERVAL '2 hours')::time
+            )
+            "#,
+        )
+        .bind(room_id)
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let response = play_in(&state, &entry_id, &room_id, false).await;
+
+        assert_eq!(response.headers()["HX-Retarget"], "#message");
+        assert!(body(response).await.contains("Sleeping time"));
+        assert!(player.commands().is_empty());
+    }
+
+    #[sqlx::test]
+    async fn play_asks_before_taking_over_a_room(db: PgPool) {
+        let (state, player) = state(db.clone());
+        let first = entry(&db, "first").await;
+        let second = entry(&db, "second").await;
+        let room_id = kitchen(&db).await;
+        play_in(&state, &first, &room_id, false).await;
+
+        let response = play_in(&state, &second, &room_id, false).await;
+
+        assert_eq!(response.headers()["HX-Retarget"], "#message");
+        assert!(body(response).await.contains("play anyway"));
+        let second_play = "media_player.kitchen: play spotify:album:second".to_string();
+        assert!(!player.commands().contains(&second_play));
+
+        let response = play_in(&state, &second, &room_id, true).await;
+
+        assert_eq!(response.headers()["HX-Redirect"], "/now-playing");
+        assert!(player.commands().contains(&second_play));
+    }
+
+    #[sqlx::test]
+    async fn play_is_refused_in_a_busy_room(db: PgPool) {
+        let (state, player) = state(db.clone());
+        let first = entry(&db, "first").await;
+        let second = entry(&db, "second").await;
+        let room_id = kitchen(&db).await;
+        sqlx::query("UPDATE rooms SET allow_takeover = FALSE WHERE id = $1")
+            .bind(room_id)
+            .execute(&db)
+            .await
+            .unwrap();
+        play_in(&state, &first, &room_id, false).await;
+
+        let response = play_in(&state, &second, &room_id, true).await;
+
+        assert!(body(response).await.contains("pick another room"));
+        assert!(
+            !player
+                .commands()
+                .contains(&"media_player.kitchen: play spotify:album:second".to_string())
+        );
+    }
+
+    #[sqlx::test]
+    async fn saving_an_entry_keeps_its_tracks(db: PgPool) {
+        let (state, _) = state(db.clone());
+        let entry_id = entry(&db, "first").await;
+        sqlx::query(
+            "INSERT INTO tracks (entry_id, track_number, spotify_id, name, duration_ms) VALUES ($1, 1, 'one', 'One', 1000), ($1, 2, 'two', 'Two', 1000)",
+        )
+        .bind(entry_id)
+        .execute(&db)
+        .await
+        .unwrap();
+
+        save(&state, &entry_id).await;
+
+        let tracks: Option<i64> =
+            sqlx::query_scalar("SELECT COUNT(*) FROM tracks WHERE entry_id = $1")
+                .bind(entry_id)
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(tracks, Some(2));
+        let name: String = sqlx::query_scalar("SELECT name FROM entries WHERE id = $1")
+            .bind(entry_id)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(name, "Renamed");
+    }
+
+    #[sqlx::test]
+    async fn saving_an_entry_keeps_its_derived_columns(db: PgPool) {
+        let (state, _) = state(db.clone());
+        let entry_id = entry(&db, "first").await;
+        sqlx::query("UPDATE entries SET explicit = TRUE, track_count = 12 WHERE id = $1")
+            .bind(entry_id)
+            .execute(&db)
+            .await
+            .unwrap();
+
+        save(&state, &entry_id).await;
+
+        let (explicit, track_count): (bool, i32) =
+            sqlx::query_as("SELECT explicit, track_count FROM entries WHERE id = $1")
+                .bind(entry_id)
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert!(explicit);
+        assert_eq!(track_count, 12);
+    }
+
+    #[sqlx::test]
+    async fn refreshing_an_entry_stores_every_track(db: PgPool) {
+        let entry_id = entry(&db, "first").await;
+        // more than the 100 items of the first playlist page
+        let items: Vec<_> = (1..=120)
+            .map(|n| json!({"track": {"id": format!("track{n}"), "name": format!("Track {n}"), "duration_ms": 1000}}))
+            .collect();
+        let source = entries::source::Source {
+            entry_type: entries::EntryType::Playlist,
+            name: "Long Playlist".to_string(),
+            image_url: String::new(),
+            spotify_uri: "spotify:playlist:first".to_string(),
+            spotify_id: "first".to_string(),
+            spotify_url: String::new(),
+            blob: json!({"tracks": {"total": items.len(), "items": items}}),
+        };
+
+        entries::update_source(&db, &entry_id, &source)
+            .await
+            .unwrap();
+
+        let track_count: i32 = sqlx::query_scalar("SELECT track_count FROM entries WHERE id = $1")
+            .bind(entry_id)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(track_count, 120);
+    }
+
+    #[test]
+    fn only_playback_outages_answer_with_503() {
+        use axum::http::StatusCode;
+        use axum::response::IntoResponse;
+
+        let unsupported =
+            errors::AppError::Playback(playback::Error::Unsupported("shuffle".to_string()));
+        assert_eq!(
+            unsupported.into_response().status(),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+        let rejected = errors::AppError::Playback(playback::Error::Status(StatusCode::BAD_REQUEST));
+        assert_eq!(
+            rejected.into_response().status(),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+        let down =
+            errors::AppError::Playback(playback::Error::Status(StatusCode::INTERNAL_SERVER_ERROR));
+        assert_eq!(
+            down.into_response().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    #[sqlx::test]
+    async fn the_remote_controls_speakers_without_a_room(db: PgPool) {
+        let (state, backend) = state(db);
+        let garage = rooms::Room::unconfigured("media_player.garage");
+        state
+            .player
+            .play(&garage, "spotify:album:first")
+            .await
+            .unwrap();
+
+        let request = Request::post("/player/pause")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(
+                "speaker=media_player.garage&speaker=media_player.unknown",
+            ))
+            .unwrap();
+        let form = Form::from_request(request, &()).await.unwrap();
+        player::handlers::control(Path("pause".to_string()), State(state), form)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            backend.commands(),
+            vec![
+                "media_player.garage: play spotify:album:first".to_string(),
+                "media_player.garage: pause".to_string(),
+            ]
+        );
+    }
 }
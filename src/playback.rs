@@ -0,0 +1,282 @@
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use strum::{AsRefStr, Display, EnumString};
+use thiserror::Error;
+
+use crate::rooms::Room;
+
+pub mod simulated;
+pub mod spotify;
+
+type Source = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("player is unreachable: {0}")]
+    Unreachable(Source),
+
+    #[error("player rejected the credentials")]
+    Unauthorized,
+
+    #[error("{0} is not available")]
+    EntityUnavailable(String),
+
+    #[error("player responded with {0}")]
+    Status(reqwest::StatusCode),
+
+    #[error("invalid response from player: {0}")]
+    InvalidResponse(Source),
+
+    #[error("{0} is not supported by this player")]
+    Unsupported(String),
+
+    #[error("player did not {0} in time")]
+    Timeout(String),
+}
+
+impl Error {
+    /// the player is reachable but cannot do what was asked, e.g. a speaker without shuffle
+    pub fn is_unsupported(&self) -> bool {
+        match self {
+            Error::Unsupported(_) => true,
+            Error::Status(status) => status.is_client_error(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PlayerState {
+    pub entity_id: String,
+    pub state: String,
+    #[serde(default)]
+    pub attributes: PlayerAttributes,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PlayerAttributes {
+    pub media_content_id: Option<String>,
+    pub media_title: Option<String>,
+    pub media_artist: Option<String>,
+    pub media_album_name: Option<String>,
+    pub entity_picture: Option<String>,
+    pub media_position: Option<f64>,
+    pub media_position_updated_at: Option<String>,
+    pub media_duration: Option<f64>,
+    pub volume_level: Option<f32>,
+    pub group_members: Option<Vec<String>>,
+}
+
+impl PlayerState {
+    pub fn is_available(&self) -> bool {
+        self.state != "unavailable"
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.state == "playing" || self.state == "paused"
+    }
+
+    /// anything that may be making a sound, including buffering players and idle ones with media loaded
+    pub fn is_on(&self) -> bool {
+        match self.state.as_str() {
+            "off" | "standby" | "unavailable" => false,
+            "idle" => self.attributes.media_content_id.is_some(),
+            _ => true,
+        }
+    }
+
+    /// players without grouping support do not report any group members
+    pub fn is_grouped(&self) -> bool {
+        self.attributes
+            .group_members
+            .as_ref()
+            .is_some_and(|m| m.len() > 1)
+    }
+}
+
+const VOLUME_STEP: f32 = 0.05;
+const TRACK_CHANGE_TIMEOUT: Duration = Duration::from_secs(10);
+const TRACK_CHANGE_POLL: Duration = Duration::from_millis(250);
+/// the kid waits for the whole resume, however many tracks have to be skipped
+const PLAY_FROM_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[derive(Debug, Clone, Copy, sqlx::Type, AsRefStr, EnumString, PartialEq, Display)]
+#[sqlx(type_name = "repeat_mode", rename_all = "lowercase")]
+pub enum Repeat {
+    #[strum(serialize = "off")]
+    Off,
+
+    #[strum(serialize = "all")]
+    All,
+
+    #[strum(serialize = "one")]
+    One,
+}
+
+#[derive(Debug, Clone, Copy, AsRefStr, EnumString, PartialEq, Display)]
+pub enum Enqueue {
+    #[strum(serialize = "next")]
+    Next,
+
+    #[strum(serialize = "add")]
+    Add,
+}
+
+/// rooms whose speaker answered the last availability poll, pages read only from here
+#[derive(Debug, Clone, Default)]
+pub struct Availability(Arc<RwLock<HashSet<sqlx::types::Uuid>>>);
+
+impl Availability {
+    pub fn filter(&self, rooms: Vec<Room>) -> Vec<Room> {
+        let available = self.0.read().unwrap_or_else(|e| e.into_inner());
+        rooms
+            .into_iter()
+            .filter(|r| available.contains(&r.id))
+            .collect()
+    }
+
+    pub fn replace(&self, rooms: &[Room]) {
+        let mut available = self.0.write().unwrap_or_else(|e| e.into_inner());
+        *available = rooms.iter().map(|r| r.id).collect();
+    }
+}
+
+/// everything the app needs to control the speakers in a room
+#[async_trait]
+pub trait Backend: Debug + Send + Sync {
+    async fn play(&self, room: &Room, spotify_uri: &str) -> Result<(), Error>;
+
+    /// lines the entry up behind the current one instead of replacing it
+    async fn enqueue(&self, room: &Room, spotify_uri: &str, enqueue: Enqueue) -> Result<(), Error>;
+
+    /// whether `Enqueue::Add` works, the entry page hides the button otherwise
+    fn supports_add_to_queue(&self) -> bool {
+        true
+    }
+
+    async fn pause(&self, room: &Room) -> Result<(), Error>;
+
+    async fn resume(&self, room: &Room) -> Result<(), Error>;
+
+    async fn stop(&self, room: &Room) -> Result<(), Error>;
+
+    async fn next(&self, room: &Room) -> Result<(), Error>;
+
+    async fn previous(&self, room: &Room) -> Result<(), Error>;
+
+    async fn seek(&self, room: &Room, position: f64) -> Result<(), Error>;
+
+    async fn shuffle_set(&self, room: &Room, shuffle: bool) -> Result<(), Error>;
+
+    async fn repeat_set(&self, room: &Room, repeat: Repeat) -> Result<(), Error>;
+
+    /// speaks the message in the room and returns once it is roughly finished
+    async fn announce(&self, room: &Room, message: &str) -> Result<(), Error>;
+
+    /// every volume change goes through here so that no one can turn a room up beyond its maximum
+    async fn volume_set(&self, room: &Room, volume: f32) -> Result<(), Error>;
+
+    async fn join(&self, leader: &Room, members: &[Room]) -> Result<(), Error>;
+
+    async fn unjoin(&self, room: &Room) -> Result<(), Error>;
+
+    /// all players the backend can reach, not only the configured rooms
+    async fn players(&self) -> Result<Vec<PlayerState>, Error>;
+
+    async fn player(&self, room: &Room) -> Result<Option<PlayerState>, Error>;
+
+    async fn volume_up(&self, room: &Room) -> Result<(), Error> {
+        let volume = self.volume(room).await?;
+        self.volume_set(room, volume + VOLUME_STEP).await
+    }
+
+    async fn volume_down(&self, room: &Room) -> Result<(), Error> {
+        let volume = self.volume(room).await?;
+        self.volume_set(room, volume - VOLUME_STEP).await
+    }
+
+    async fn volume(&self, room: &Room) -> Result<f32, Error> {
+        Ok(self
+            .player(room)
+            .await?
+            .and_then(|p| p.attributes.volume_level)
+            .unwrap_or(room.default_volume))
+    }
+
+    /// starting an album has no offset, so the player skips forward to the track before seeking
+    async fn play_from(
+        &self,
+        room: &Room,
+        spotify_uri: &str,
+        track_number: i32,
+        position: f64,
+    ) -> Result<(), Error> {
+        let previous = self.media_content_id(room).await?;
+        self.play(room, spotify_uri).await?;
+        if track_number <= 1 && position <= 0.0 {
+            return Ok(());
+        }
+
+        tokio::time::timeout(PLAY_FROM_TIMEOUT, async {
+            // the same track may already have been playing, then there is no change to wait for
+            let mut current = match self.track_changed(room, previous.clone()).await {
+                Err(Error::Timeout(_)) => previous,
+                current => current?,
+            };
+            for _ in 1..track_number {
+                self.next(room).await?;
+                current = self.track_changed(room, current).await?;
+            }
+            if position > 0.0 {
+                self.seek(room, position).await?;
+            }
+            Ok(())
+        })
+        .await
+        .map_err(|_| Error::Timeout("reach the saved track".to_string()))?
+    }
+
+    async fn media_content_id(&self, room: &Room) -> Result<Option<String>, Error> {
+        Ok(self
+            .player(room)
+            .await?
+            .and_then(|p| p.attributes.media_content_id))
+    }
+
+    /// speakers ignore skips until the queue has loaded, so every step waits for the track to change
+    async fn track_changed(
+        &self,
+        room: &Room,
+        previous: Option<String>,
+    ) -> Result<Option<String>, Error> {
+        tokio::time::timeout(TRACK_CHANGE_TIMEOUT, async {
+            loop {
+                let current = self.media_content_id(room).await?;
+                if current.is_some() && current != previous {
+                    return Ok(current);
+                }
+                tokio::time::sleep(TRACK_CHANGE_POLL).await;
+            }
+        })
+        .await
+        .map_err(|_| Error::Timeout("change the track".to_string()))?
+    }
+
+    async fn available_rooms(&self, rooms: Vec<Room>) -> Result<Vec<Room>, Error> {
+        let players = self.players().await?;
+
+        Ok(rooms
+            .into_iter()
+            .filter(|r| {
+                players
+                    .iter()
+                    .any(|p| p.entity_id == r.entity_id && p.is_available())
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use tracing::info;
+
+use crate::rooms::Room;
+
+use super::{Backend, Enqueue, Error, PlayerAttributes, PlayerState, Repeat};
+
+/// in-process player for demos, local development and tests, it only records the commands it receives
+#[derive(Debug, Default)]
+pub struct Player {
+    players: Mutex<HashMap<String, PlayerState>>,
+    commands: Mutex<Vec<String>>,
+}
+
+impl Player {
+    /// every command so far as `entity_id: command`, oldest first
+    pub fn commands(&self) -> Vec<String> {
+        self.commands
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn record(&self, room: &Room, command: &str, apply: impl FnOnce(&mut PlayerState)) {
+        info!("simulated {} in {}", command, room.entity_id);
+        self.commands
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(format!("{}: {}", room.entity_id, command));
+        let mut players = self.players.lock().unwrap_or_else(|e| e.into_inner());
+        let player = players
+            .entry(room.entity_id.clone())
+            .or_insert_with(|| idle(room));
+        apply(player);
+    }
+}
+
+fn idle(room: &Room) -> PlayerState {
+    PlayerState {
+        entity_id: room.entity_id.clone(),
+        state: "idle".to_string(),
+        attributes: PlayerAttributes {
+            volume_level: Some(room.default_volume),
+            ..Default::default()
+        },
+    }
+}
+
+#[async_trait]
+impl Backend for Player {
+    async fn play(&self, room: &Room, spotify_uri: &str) -> Result<(), Error> {
+        self.record(room, &format!("play {}", spotify_uri), |p| {
+            p.state = "playing".to_string();
+            p.attributes.media_content_id = Some(spotify_uri.to_string());
+            p.attributes.media_position = Some(0.0);
+            p.attributes.volume_level = Some(room.default_volume);
+        });
+        Ok(())
+    }
+
+    /// the simulated speaker has no queue to wait for, it starts at the track right away
+    async fn play_from(
+        &self,
+        room: &Room,
+        spotify_uri: &str,
+        track_number: i32,
+        position: f64,
+    ) -> Result<(), Error> {
+        self.record(
+            room,
+            &format!("play_from {} {} {}", spotify_uri, track_number, position),
+            |p| {
+                p.state = "playing".to_string();
+                p.attributes.media_content_id = Some(spotify_uri.to_string());
+                p.attributes.media_position = Some(position);
+                p.attributes.volume_level = Some(room.default_volume);
+            },
+        );
+        Ok(())
+    }
+
+    async fn enqueue(&self, room: &Room, spotify_uri: &str, enqueue: Enqueue) -> Result<(), Error> {
+        self.record(
+            room,
+            &format!("enqueue {} {}", enqueue, spotify_uri),
+            |_| {},
+        );
+        Ok(())
+    }
+
+    async fn pause(&self, room: &Room) -> Result<(), Error> {
+        self.record(room, "pause", |p| p.state = "paused".to_string());
+        Ok(())
+    }
+
+    async fn resume(&self, room: &Room) -> Result<(), Error> {
+        self.record(room, "resume", |p| p.state = "playing".to_string());
+        Ok(())
+    }
+
+    async fn stop(&self, room: &Room) -> Result<(), Error> {
+        self.record(room, "stop", |p| {
+            p.state = "idle".to_string();
+            p.attributes.media_content_id = None;
+            p.attributes.media_position = None;
+        });
+        Ok(())
+    }
+
+    async fn next(&self, room: &Room) -> Result<(), Error> {
+        self.record(room, "next", |p| p.attributes.media_position = Some(0.0));
+        Ok(())
+    }
+
+    async fn previous(&self, room: &Room) -> Result<(), Error> {
+        self.record(room, "previous", |p| {
+            p.attributes.media_position = Some(0.0)
+        });
+        Ok(())
+    }
+
+    async fn seek(&self, room: &Room, position: f64) -> Result<(), Error> {
+        self.record(room, "seek", |p| {
+            p.attributes.media_position = Some(position)
+        });
+        Ok(())
+    }
+
+    async fn shuffle_set(&self, room: &Room, shuffle: bool) -> Result<(), Error> {
+        self.record(room, &format!("shuffle_set {}", shuffle), |_| {});
+        Ok(())
+    }
+
+    async fn repeat_set(&self, room: &Room, repeat: Repeat) -> Result<(), Error> {
+        self.record(room, &format!("repeat_set {}", repeat), |_| {});
+        Ok(())
+    }
+
+    async fn announce(&self, room: &Room, message: &str) -> Result<(), Error> {
+        self.record(room, &format!("announce \"{}\"", message), |_| {});
+        Ok(())
+    }
+
+    async fn volume_set(&self, room: &Room, volume: f32) -> Result<(), Error> {
+        let volume = room.clamp_volume(volume);
+        self.record(room, "volume_set", |p| {
+            p.attributes.volume_level = Some(volume)
+        });
+        Ok(())
+    }
+
+    async fn join(&self, leader: &Room, members: &[Room]) -> Result<(), Error> {
+        for member in members {
+            self.volume_set(member, member.default_volume).await?;
+            self.record(member, &format!("join {}", leader.entity_id), |_| {});
+        }
+        Ok(())
+    }
+
+    async fn unjoin(&self, room: &Room) -> Result<(), Error> {
+        self.record(room, "unjoin", |_| {});
+        Ok(())
+    }
+
+    async fn players(&self) -> Result<Vec<PlayerState>, Error> {
+        let players = self.players.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(players.values().cloned().collect())
+    }
+
+    async fn player(&self, room: &Room) -> Result<Option<PlayerState>, Error> {
+        let players = self.players.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(Some(
+            players
+                .get(&room.entity_id)
+                .cloned()
+                .unwrap_or_else(|| idle(room)),
+        ))
+    }
+
+    /// every configured room has a simulated speaker
+    async fn available_rooms(&self, rooms: Vec<Room>) -> Result<Vec<Room>, Error> {
+        Ok(rooms)
+    }
+}
@@ -0,0 +1,363 @@
+use async_trait::async_trait;
+use chrono::TimeDelta;
+use futures_util::TryStreamExt;
+use rspotify::clients::{BaseClient, OAuthClient};
+use rspotify::http::HttpError;
+use rspotify::model::{
+    AdditionalType, AlbumId, CurrentPlaybackContext, Device, Id, Offset, PlayContextId, PlayableId,
+    PlayableItem, PlaylistId, RepeatState, Token,
+};
+use rspotify::{AuthCodeSpotify, ClientError, Config, Credentials, OAuth, scopes};
+use sqlx::PgPool;
+
+use crate::rooms::Room;
+
+use super::{Backend, Enqueue, Error, PlayerAttributes, PlayerState, Repeat};
+
+pub mod handlers;
+
+/// the user client is only available when a redirect uri for the authorization code flow is configured
+pub fn client() -> Option<AuthCodeSpotify> {
+    let creds = Credentials::from_env()?;
+    let oauth = OAuth::from_env(scopes!(
+        "user-read-playback-state",
+        "user-modify-playback-state"
+    ))?;
+    Some(AuthCodeSpotify::with_config(
+        creds,
+        oauth,
+        Config {
+            token_refreshing: true,
+            ..Default::default()
+        },
+    ))
+}
+
+pub async fn save_refresh_token(db: &PgPool, refresh_token: &str) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO spotify_tokens (refresh_token)
+        VALUES ($1)
+        ON CONFLICT (id) DO UPDATE SET refresh_token = $1, updated_at = NOW()
+        "#,
+        refresh_token
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+async fn get_refresh_token(db: &PgPool) -> anyhow::Result<Option<String>> {
+    let rec = sqlx::query!(
+        r#"
+        SELECT refresh_token
+        FROM spotify_tokens
+        "#
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(rec.map(|r| r.refresh_token))
+}
+
+fn to_error(err: ClientError) -> Error {
+    match err {
+        ClientError::InvalidToken => Error::Unauthorized,
+        ClientError::Http(err) => match *err {
+            HttpError::StatusCode(res) if res.status() == reqwest::StatusCode::UNAUTHORIZED => {
+                Error::Unauthorized
+            }
+            HttpError::StatusCode(res) => Error::Status(res.status()),
+            err => Error::Unreachable(err.into()),
+        },
+        err => Error::InvalidResponse(err.into()),
+    }
+}
+
+fn context_id(spotify_uri: &str) -> Result<PlayContextId<'_>, Error> {
+    if let Ok(id) = AlbumId::from_uri(spotify_uri) {
+        return Ok(PlayContextId::Album(id));
+    }
+    PlaylistId::from_uri(spotify_uri)
+        .map(PlayContextId::Playlist)
+        .map_err(|_| Error::Unsupported(spotify_uri.to_string()))
+}
+
+fn player_state(device: Device, playback: Option<&CurrentPlaybackContext>) -> PlayerState {
+    let mut attributes = PlayerAttributes {
+        volume_level: device.volume_percent.map(|v| v as f32 / 100.0),
+        ..Default::default()
+    };
+    let playback = playback.filter(|p| p.device.id.is_some() && p.device.id == device.id);
+    let state = match playback {
+        _ if device.is_restricted => "unavailable",
+        Some(playback) => {
+            if let Some(PlayableItem::Track(track)) = &playback.item {
+                attributes.media_content_id = track.id.as_ref().map(|id| id.uri());
+                attributes.media_title = Some(track.name.clone());
+                attributes.media_artist = track.artists.first().map(|a| a.name.clone());
+                attributes.media_album_name = Some(track.album.name.clone());
+                attributes.entity_picture = track.album.images.first().map(|i| i.url.clone());
+                attributes.media_duration = Some(track.duration.num_milliseconds() as f64 / 1000.0);
+            }
+            attributes.media_position = playback
+                .progress
+                .map(|p| p.num_milliseconds() as f64 / 1000.0);
+            attributes.media_position_updated_at = Some(playback.timestamp.to_rfc3339());
+            if playback.is_playing {
+                "playing"
+            } else {
+                "paused"
+            }
+        }
+        None => "idle",
+    };
+
+    PlayerState {
+        entity_id: device.name,
+        state: state.to_string(),
+        attributes,
+    }
+}
+
+/// plays on spotify connect devices directly, a room's entity id holds the device name
+#[derive(Debug, Clone)]
+pub struct Player {
+    db: PgPool,
+    client: AuthCodeSpotify,
+}
+
+impl Player {
+    pub fn new(db: PgPool, client: AuthCodeSpotify) -> Player {
+        Player { db, client }
+    }
+
+    /// after a restart the access token is gone, so it is fetched again with the stored refresh token
+    async fn authorize(&self) -> Result<(), Error> {
+        {
+            let mut token = self
+                .client
+                .token
+                .lock()
+                .await
+                .map_err(|_| Error::Unauthorized)?;
+            if token.is_some() {
+                return Ok(());
+            }
+            let refresh_token = get_refresh_token(&self.db)
+                .await
+                .map_err(|e| Error::Unreachable(e.into()))?
+                .ok_or(Error::Unauthorized)?;
+            *token = Some(Token {
+                refresh_token: Some(refresh_token),
+                ..Default::default()
+            });
+        }
+        self.client.refresh_token().await.map_err(to_error)
+    }
+
+    /// the queue only takes single tracks, so albums and playlists are expanded first
+    async fn tracks(&self, spotify_uri: &str) -> Result<Vec<PlayableId<'static>>, Error> {
+        let tracks = match context_id(spotify_uri)? {
+            PlayContextId::Album(id) => self
+                .client
+                .album_track(id, None)
+                .try_collect::<Vec<_>>()
+                .await
+                .map_err(to_error)?
+                .into_iter()
+                .filter_map(|t| t.id.map(PlayableId::Track))
+                .collect(),
+            PlayContextId::Playlist(id) => self
+                .client
+                .playlist_items(id, None, None)
+                .try_collect::<Vec<_>>()
+                .await
+                .map_err(to_error)?
+                .into_iter()
+                .filter_map(|i| i.track.and_then(|t| t.id().map(|id| id.into_static())))
+                .collect(),
+            _ => return Err(Error::Unsupported(spotify_uri.to_string())),
+        };
+        Ok(tracks)
+    }
+
+    async fn device_id(&self, room: &Room) -> Result<String, Error> {
+        self.authorize().await?;
+        self.client
+            .device()
+            .await
+            .map_err(to_error)?
+            .into_iter()
+            .find(|d| d.name == room.entity_id && !d.is_restricted)
+            .and_then(|d| d.id)
+            .ok_or_else(|| Error::EntityUnavailable(room.entity_id.clone()))
+    }
+}
+
+#[async_trait]
+impl Backend for Player {
+    async fn play(&self, room: &Room, spotify_uri: &str) -> Result<(), Error> {
+        self.play_from(room, spotify_uri, 1, 0.0).await
+    }
+
+    /// unlike home assistant, spotify takes the track offset and position with the play request
+    async fn play_from(
+        &self,
+        room: &Room,
+        spotify_uri: &str,
+        track_number: i32,
+        position: f64,
+    ) -> Result<(), Error> {
+        let device_id = self.device_id(room).await?;
+        self.volume_set(room, room.default_volume).await?;
+        // the track offset is a plain index, rspotify only happens to model it as a duration
+        self.client
+            .start_context_playback(
+                context_id(spotify_uri)?,
+                Some(&device_id),
+                Some(Offset::Position(TimeDelta::milliseconds(
+                    i64::from(track_number.max(1)) - 1,
+                ))),
+                Some(TimeDelta::milliseconds((position * 1000.0) as i64)),
+            )
+            .await
+            .map_err(to_error)
+    }
+
+    fn supports_add_to_queue(&self) -> bool {
+        false
+    }
+
+    /// queued tracks always play before the current album continues, so only next can be honoured
+    async fn enqueue(&self, room: &Room, spotify_uri: &str, enqueue: Enqueue) -> Result<(), Error> {
+        if enqueue == Enqueue::Add {
+            return Err(Error::Unsupported(
+                "adding behind the current album".to_string(),
+            ));
+        }
+        let device_id = self.device_id(room).await?;
+        for track in self.tracks(spotify_uri).await? {
+            self.client
+                .add_item_to_queue(track, Some(&device_id))
+                .await
+                .map_err(to_error)?;
+        }
+        Ok(())
+    }
+
+    async fn pause(&self, room: &Room) -> Result<(), Error> {
+        let device_id = self.device_id(room).await?;
+        self.client
+            .pause_playback(Some(&device_id))
+            .await
+            .map_err(to_error)
+    }
+
+    async fn resume(&self, room: &Room) -> Result<(), Error> {
+        let device_id = self.device_id(room).await?;
+        self.client
+            .resume_playback(Some(&device_id), None)
+            .await
+            .map_err(to_error)
+    }
+
+    /// spotify connect has no stop, pausing is the closest thing
+    async fn stop(&self, room: &Room) -> Result<(), Error> {
+        self.pause(room).await
+    }
+
+    async fn next(&self, room: &Room) -> Result<(), Error> {
+        let device_id = self.device_id(room).await?;
+        self.client
+            .next_track(Some(&device_id))
+            .await
+            .map_err(to_error)
+    }
+
+    async fn previous(&self, room: &Room) -> Result<(), Error> {
+        let device_id = self.device_id(room).await?;
+        self.client
+            .previous_track(Some(&device_id))
+            .await
+            .map_err(to_error)
+    }
+
+    async fn seek(&self, room: &Room, position: f64) -> Result<(), Error> {
+        let device_id = self.device_id(room).await?;
+        self.client
+            .seek_track(
+                TimeDelta::milliseconds((position * 1000.0) as i64),
+                Some(&device_id),
+            )
+            .await
+            .map_err(to_error)
+    }
+
+    async fn shuffle_set(&self, room: &Room, shuffle: bool) -> Result<(), Error> {
+        let device_id = self.device_id(room).await?;
+        self.client
+            .shuffle(shuffle, Some(&device_id))
+            .await
+            .map_err(to_error)
+    }
+
+    async fn repeat_set(&self, room: &Room, repeat: Repeat) -> Result<(), Error> {
+        let device_id = self.device_id(room).await?;
+        let state = match repeat {
+            Repeat::Off => RepeatState::Off,
+            Repeat::All => RepeatState::Context,
+            Repeat::One => RepeatState::Track,
+        };
+        self.client
+            .repeat(state, Some(&device_id))
+            .await
+            .map_err(to_error)
+    }
+
+    async fn volume_set(&self, room: &Room, volume: f32) -> Result<(), Error> {
+        let device_id = self.device_id(room).await?;
+        let percent = (room.clamp_volume(volume) * 100.0).round() as u8;
+        self.client
+            .volume(percent, Some(&device_id))
+            .await
+            .map_err(to_error)
+    }
+
+    async fn announce(&self, _room: &Room, _message: &str) -> Result<(), Error> {
+        Err(Error::Unsupported("announcements".to_string()))
+    }
+
+    /// a spotify account can only play on one device at a time
+    async fn join(&self, _leader: &Room, _members: &[Room]) -> Result<(), Error> {
+        Err(Error::Unsupported("speaker groups".to_string()))
+    }
+
+    async fn unjoin(&self, _room: &Room) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn players(&self) -> Result<Vec<PlayerState>, Error> {
+        self.authorize().await?;
+        let devices = self.client.device().await.map_err(to_error)?;
+        let playback = self
+            .client
+            .current_playback(None, None::<Vec<&AdditionalType>>)
+            .await
+            .map_err(to_error)?;
+
+        Ok(devices
+            .into_iter()
+            .map(|d| player_state(d, playback.as_ref()))
+            .collect())
+    }
+
+    async fn player(&self, room: &Room) -> Result<Option<PlayerState>, Error> {
+        Ok(self
+            .players()
+            .await?
+            .into_iter()
+            .find(|p| p.entity_id == room.entity_id))
+    }
+}
@@ -0,0 +1,54 @@
+use anyhow::anyhow;
+use axum::{
+    extract::State,
+    response::{IntoResponse, Redirect},
+};
+use axum_extra::extract::Query;
+use rspotify::clients::OAuthClient;
+use serde::Deserialize;
+use tracing::info;
+
+use crate::{errors, states};
+
+pub async fn login(
+    State(state): State<states::AppState>,
+) -> Result<impl IntoResponse, errors::AppError> {
+    let client = state
+        .spotify_auth
+        .as_ref()
+        .ok_or(errors::AppError::NotFound)?;
+    Ok(Redirect::to(&client.get_authorize_url(false)?))
+}
+
+#[derive(Deserialize)]
+pub struct CallbackQuery {
+    code: String,
+    state: String,
+}
+
+pub async fn callback(
+    State(state): State<states::AppState>,
+    Query(callback_query): Query<CallbackQuery>,
+) -> Result<impl IntoResponse, errors::AppError> {
+    let client = state
+        .spotify_auth
+        .as_ref()
+        .ok_or(errors::AppError::NotFound)?;
+    if callback_query.state != client.oauth.state {
+        return Err(anyhow!("spotify authorization state does not match").into());
+    }
+
+    client.request_token(&callback_query.code).await?;
+    let refresh_token = client
+        .token
+        .lock()
+        .await
+        .map_err(|_| anyhow!("could not read the spotify token"))?
+        .as_ref()
+        .and_then(|t| t.refresh_token.clone())
+        .ok_or(anyhow!("spotify did not return a refresh token"))?;
+    super::save_refresh_token(&state.db, &refresh_token).await?;
+    info!("connected spotify account");
+
+    Ok(Redirect::to("/admin/rooms"))
+}
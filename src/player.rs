@@ -0,0 +1,333 @@
+use std::time::Duration;
+
+use serde_json::json;
+use sqlx::PgPool;
+use strum::{AsRefStr, Display, EnumString};
+use tracing::{error, info};
+
+use crate::{
+    categories,
+    entries::{self, EntryListModel},
+    playback::{Backend, PlayerState},
+    rooms::{self, Room},
+    sleep_timers, states,
+};
+
+pub mod handlers;
+
+#[derive(Debug, AsRefStr, EnumString, PartialEq, Display)]
+pub enum Command {
+    #[strum(serialize = "pause")]
+    Pause,
+
+    #[strum(serialize = "resume")]
+    Resume,
+
+    #[strum(serialize = "stop")]
+    Stop,
+
+    #[strum(serialize = "next")]
+    Next,
+
+    #[strum(serialize = "previous")]
+    Previous,
+
+    #[strum(serialize = "volume-up")]
+    VolumeUp,
+
+    #[strum(serialize = "volume-down")]
+    VolumeDown,
+}
+
+#[derive(Debug)]
+pub struct NowPlayingModel {
+    pub room: Room,
+    pub state: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub image_url: Option<String>,
+    pub progress: Option<u8>,
+    pub entry: Option<EntryListModel>,
+    pub sleep_minutes_left: Option<i64>,
+}
+
+/// the players are fetched by the caller so that an unreachable backend keeps its typed error
+pub async fn now_playing(
+    db: &PgPool,
+    players: &[PlayerState],
+    rooms: Vec<Room>,
+) -> anyhow::Result<Vec<NowPlayingModel>> {
+    let sleep_timers = sleep_timers::list_all(db).await?;
+
+    let mut result = vec![];
+    for room in rooms {
+        let Some(player) = players.iter().find(|p| p.entity_id == room.entity_id) else {
+            continue;
+        };
+        if !player.is_active() {
+            continue;
+        }
+
+        let attributes = &player.attributes;
+        let entry = match &attributes.media_content_id {
+            Some(media_content_id) => {
+                entries::find_by_media_content_id(db, media_content_id).await?
+            }
+            None => None,
+        };
+        let progress = attributes
+            .media_position
+            .zip(attributes.media_duration)
+            .filter(|(_, duration)| *duration > 0.0)
+            .map(|(position, duration)| (position / duration * 100.0).clamp(0.0, 100.0) as u8);
+
+        result.push(NowPlayingModel {
+            image_url: entry
+                .as_ref()
+                .map(|e| e.image_url.clone())
+                .or_else(|| attributes.entity_picture.clone()),
+            title: attributes.media_title.clone(),
+            artist: attributes.media_artist.clone(),
+            state: player.state.clone(),
+            progress,
+            entry,
+            sleep_minutes_left: sleep_timers
+                .iter()
+                .find(|t| t.room_id == room.id)
+                .map(|t| t.minutes_left),
+            room,
+        });
+    }
+
+    Ok(result)
+}
+
+#[derive(Debug)]
+pub struct RemoteModel {
+    pub entity_id: String,
+    pub room: Option<Room>,
+    pub state: String,
+    pub title: Option<String>,
+    pub volume: Option<u8>,
+    pub entry: Option<EntryListModel>,
+}
+
+impl RemoteModel {
+    pub fn is_active(&self) -> bool {
+        self.state == "playing" || self.state == "paused"
+    }
+
+    /// the controls address rooms by id and other speakers by entity id
+    pub fn control_vals(&self) -> String {
+        match &self.room {
+            Some(room) => json!({"room": room.id.to_string()}).to_string(),
+            None => json!({"speaker": self.entity_id}).to_string(),
+        }
+    }
+}
+
+/// every speaker the backend knows, configured rooms first and unreachable rooms included
+pub async fn remote(
+    db: &PgPool,
+    players: &[PlayerState],
+    rooms: Vec<Room>,
+) -> anyhow::Result<Vec<RemoteModel>> {
+    let unconfigured = players
+        .iter()
+        .filter(|p| rooms.iter().all(|r| r.entity_id != p.entity_id))
+        .map(|p| (p.entity_id.clone(), None))
+        .collect::<Vec<_>>();
+    let speakers = rooms
+        .into_iter()
+        .map(|r| (r.entity_id.clone(), Some(r)))
+        .chain(unconfigured);
+
+    let mut result = vec![];
+    for (entity_id, room) in speakers {
+        let player = players.iter().find(|p| p.entity_id == entity_id);
+        let entry = match player.and_then(|p| p.attributes.media_content_id.as_ref()) {
+            Some(media_content_id) => {
+                entries::find_by_media_content_id(db, media_content_id).await?
+            }
+            None => None,
+        };
+        result.push(RemoteModel {
+            state: player.map_or_else(|| "unavailable".to_owned(), |p| p.state.clone()),
+            title: player.and_then(|p| p.attributes.media_title.clone()),
+            volume: player
+                .and_then(|p| p.attributes.volume_level)
+                .map(|v| (v.clamp(0.0, 1.0) * 100.0).round() as u8),
+            entry,
+            entity_id,
+            room,
+        });
+    }
+
+    Ok(result)
+}
+
+#[derive(Debug)]
+pub struct PlaybackPosition {
+    pub track_number: i32,
+    pub position: f64,
+}
+
+pub async fn get_position(
+    db: &PgPool,
+    entry_id: &sqlx::types::Uuid,
+    room_id: &sqlx::types::Uuid,
+) -> anyhow::Result<Option<PlaybackPosition>> {
+    let result = sqlx::query_as!(
+        PlaybackPosition,
+        r#"
+        SELECT track_number, position
+        FROM playback_positions
+        WHERE entry_id = $1 AND room_id = $2
+        "#,
+        entry_id,
+        room_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(result)
+}
+
+async fn save_position(
+    db: &PgPool,
+    entry_id: &sqlx::types::Uuid,
+    room_id: &sqlx::types::Uuid,
+    track_number: i32,
+    player: &PlayerState,
+) -> anyhow::Result<()> {
+    // the reported position is only as fresh as its update timestamp, postgres adds the time since then
+    sqlx::query!(
+        r#"
+        INSERT INTO playback_positions (entry_id, room_id, track_number, position)
+        VALUES (
+            $1, $2, $3,
+            $4 + CASE
+                WHEN $5 AND $6::text IS NOT NULL THEN GREATEST(EXTRACT(EPOCH FROM NOW() - $6::timestamptz)::float8, 0)
+                ELSE 0
+            END
+        )
+        ON CONFLICT (entry_id, room_id) DO UPDATE
+        SET track_number = EXCLUDED.track_number, position = EXCLUDED.position, updated_at = NOW()
+        "#,
+        entry_id,
+        room_id,
+        track_number,
+        player.attributes.media_position.unwrap_or_default(),
+        player.state == "playing",
+        player.attributes.media_position_updated_at
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete_position(
+    db: &PgPool,
+    entry_id: &sqlx::types::Uuid,
+    room_id: &sqlx::types::Uuid,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        DELETE FROM playback_positions
+        WHERE entry_id = $1 AND room_id = $2
+        "#,
+        entry_id,
+        room_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+async fn record_positions(db: &PgPool, backend: &dyn Backend) -> anyhow::Result<()> {
+    let players = backend.players().await?;
+    for room in rooms::list_enabled(db).await? {
+        let Some(player) = players
+            .iter()
+            .find(|p| p.entity_id == room.entity_id && p.is_active())
+        else {
+            continue;
+        };
+        let Some(media_content_id) = &player.attributes.media_content_id else {
+            continue;
+        };
+        let Some(track) = entries::find_audiobook_track(db, media_content_id).await? else {
+            continue;
+        };
+
+        // a finished audiobook starts from the beginning the next time
+        let remaining = f64::from(track.duration_ms) / 1000.0
+            - player.attributes.media_position.unwrap_or_default();
+        if i64::from(track.track_number) >= track.track_count && remaining < 30.0 {
+            delete_position(db, &track.entry_id, &room.id).await?;
+            continue;
+        }
+
+        save_position(db, &track.entry_id, &room.id, track.track_number, player).await?;
+    }
+    Ok(())
+}
+
+async fn record_queued_plays(db: &PgPool, backend: &dyn Backend) -> anyhow::Result<()> {
+    let players = backend.players().await?;
+    for room in rooms::list_enabled(db).await? {
+        let Some(media_content_id) = players
+            .iter()
+            .find(|p| p.entity_id == room.entity_id && p.state == "playing")
+            .and_then(|p| p.attributes.media_content_id.as_ref())
+        else {
+            continue;
+        };
+        let Some(entry) = entries::take_queued(db, &room.id, media_content_id).await? else {
+            continue;
+        };
+
+        rooms::record_play(db, &room.id, &entry.id).await?;
+        entries::increment_play_count(db, &entry.id.to_string()).await?;
+        if let Some(category_id) = &entry.category_id {
+            categories::set_last_entry(db, category_id, &entry.id, &room.id).await?;
+        }
+        info!("queued {} started in {}", entry.name, room.name);
+    }
+    Ok(())
+}
+
+async fn refresh_availability(state: &states::AppState) -> anyhow::Result<()> {
+    let rooms = rooms::list_enabled(&state.db).await?;
+    let available = state.player.available_rooms(rooms).await?;
+    state.availability.replace(&available);
+    Ok(())
+}
+
+/// a failed poll keeps the last known availability so that a hiccup does not hide every room
+pub async fn track_availability(state: states::AppState) {
+    info!("tracking room availability");
+    let mut interval = tokio::time::interval(Duration::from_secs(10));
+    loop {
+        interval.tick().await;
+        if let Err(err) = refresh_availability(&state).await {
+            error!("could not refresh room availability: {}", err);
+        }
+    }
+}
+
+pub async fn track_positions(state: states::AppState) {
+    info!("tracking audiobook positions");
+    let mut interval = tokio::time::interval(Duration::from_secs(30));
+    loop {
+        interval.tick().await;
+        if let Err(err) = record_positions(&state.db, state.player.as_ref()).await {
+            error!("could not record audiobook positions: {}", err);
+        }
+        if let Err(err) = record_queued_plays(&state.db, state.player.as_ref()).await {
+            error!("could not record queued plays: {}", err);
+        }
+    }
+}
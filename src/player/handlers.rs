@@ -0,0 +1,129 @@
+use std::str::FromStr;
+
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{Html, IntoResponse},
+};
+use axum_extra::extract::Form;
+use serde::Deserialize;
+use tracing::{error, info};
+
+use crate::{
+    errors,
+    rooms::{self, Room},
+    sleep_timers, states,
+};
+
+use super::{Command, NowPlayingModel, RemoteModel};
+
+#[derive(Deserialize, Debug)]
+pub struct ControlForm {
+    #[serde(default)]
+    room: Vec<String>,
+    /// speakers that are not set up as rooms, only the admin remote sends these
+    #[serde(default)]
+    speaker: Vec<String>,
+}
+
+pub async fn control(
+    Path(command): Path<String>,
+    State(state): State<states::AppState>,
+    Form(control_form): Form<ControlForm>,
+) -> Result<impl IntoResponse, errors::AppError> {
+    let command = Command::from_str(&command)?;
+    let mut rooms = rooms::list_by_ids(&state.db, &control_form.room).await?;
+    if !control_form.speaker.is_empty() {
+        let players = state.player.players().await?;
+        rooms.extend(
+            players
+                .iter()
+                .filter(|p| control_form.speaker.contains(&p.entity_id))
+                .map(|p| Room::unconfigured(&p.entity_id)),
+        );
+    }
+    for room in &rooms {
+        match command {
+            Command::Pause => state.player.pause(room).await?,
+            Command::Resume => state.player.resume(room).await?,
+            Command::Stop => {
+                state.player.stop(room).await?;
+                state.player.unjoin(room).await?;
+                sleep_timers::delete(&state.db, &room.id).await?
+            }
+            Command::Next => state.player.next(room).await?,
+            Command::Previous => state.player.previous(room).await?,
+            Command::VolumeUp => state.player.volume_up(room).await?,
+            Command::VolumeDown => state.player.volume_down(room).await?,
+        }
+        info!("sent {} to {}", command, room.name);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Template)]
+#[template(path = "now_playing.html")]
+struct NowPlayingTemplate {
+    players: Vec<NowPlayingModel>,
+}
+
+pub async fn now_playing(
+    State(state): State<states::AppState>,
+) -> Result<impl IntoResponse, errors::AppError> {
+    let rooms = rooms::list_enabled(&state.db).await?;
+    let players = state.player.players().await?;
+    let players = super::now_playing(&state.db, &players, rooms).await?;
+    Ok(Html(NowPlayingTemplate { players }.render()?))
+}
+
+#[derive(Template)]
+#[template(path = "admin_remote.html")]
+struct RemoteTemplate {
+    players: Vec<RemoteModel>,
+}
+
+pub async fn admin_remote(
+    State(state): State<states::AppState>,
+) -> Result<impl IntoResponse, errors::AppError> {
+    let rooms = rooms::list_all(&state.db).await?;
+    let players = state.player.players().await?;
+    let players = super::remote(&state.db, &players, rooms).await?;
+    Ok(Html(RemoteTemplate { players }.render()?))
+}
+
+/// one speaker failing must not keep the others playing, so the first error is reported at the end
+pub async fn admin_stop_all(
+    State(state): State<states::AppState>,
+) -> Result<impl IntoResponse, errors::AppError> {
+    let rooms = rooms::list_all(&state.db).await?;
+    let players = state.player.players().await?;
+
+    let mut failure = None;
+    for player in players.iter().filter(|p| p.is_on()) {
+        let room = rooms.iter().find(|r| r.entity_id == player.entity_id);
+        let speaker = room
+            .cloned()
+            .unwrap_or_else(|| Room::unconfigured(&player.entity_id));
+        let stopped = async {
+            state.player.stop(&speaker).await?;
+            state.player.unjoin(&speaker).await
+        }
+        .await;
+        if let Err(err) = stopped {
+            error!("could not stop {}: {}", speaker.name, err);
+            failure.get_or_insert(err);
+            continue;
+        }
+        if let Some(room) = room {
+            sleep_timers::delete(&state.db, &room.id).await?;
+        }
+        info!("stopped {}", speaker.name);
+    }
+
+    match failure {
+        Some(err) => Err(err.into()),
+        None => Ok(StatusCode::NO_CONTENT),
+    }
+}
@@ -0,0 +1,354 @@
+use sqlx::{PgPool, types::Uuid};
+
+pub mod handlers;
+
+#[derive(Debug, sqlx::FromRow, Clone, PartialEq)]
+pub struct Room {
+    pub id: sqlx::types::Uuid,
+    pub name: String,
+    pub entity_id: String,
+    pub icon_url: String,
+    pub sort_order: i32,
+    pub enabled: bool,
+    pub default_volume: f32,
+    pub max_volume: f32,
+    pub allow_takeover: bool,
+    pub announce: bool,
+}
+
+impl Room {
+    /// stands in for a speaker that is not set up as a room, nothing limits its volume
+    pub fn unconfigured(entity_id: &str) -> Room {
+        Room {
+            id: Uuid::nil(),
+            name: entity_id.to_owned(),
+            entity_id: entity_id.to_owned(),
+            icon_url: String::new(),
+            sort_order: 0,
+            enabled: false,
+            default_volume: 0.0,
+            max_volume: 1.0,
+            allow_takeover: true,
+            announce: false,
+        }
+    }
+
+    pub fn clamp_volume(&self, volume: f32) -> f32 {
+        volume.clamp(0.0, self.max_volume)
+    }
+}
+
+pub async fn list_all(db: &PgPool) -> anyhow::Result<Vec<Room>> {
+    let result = sqlx::query_as!(
+        Room,
+        r#"
+        SELECT 
+            id, name, entity_id, icon_url, sort_order, enabled, default_volume, max_volume,
+            allow_takeover, announce
+        FROM rooms
+        ORDER BY sort_order, name
+        "#,
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(result)
+}
+
+pub async fn list_enabled(db: &PgPool) -> anyhow::Result<Vec<Room>> {
+    let result = sqlx::query_as!(
+        Room,
+        r#"
+        SELECT 
+            id, name, entity_id, icon_url, sort_order, enabled, default_volume, max_volume,
+            allow_takeover, announce
+        FROM rooms
+        WHERE enabled = TRUE
+        ORDER BY sort_order, name
+        "#,
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(result)
+}
+
+pub async fn get(db: &PgPool, room_id: &str) -> anyhow::Result<Room> {
+    let id = sqlx::types::Uuid::parse_str(room_id)?;
+    let result = sqlx::query_as!(
+        Room,
+        r#"
+        SELECT 
+            id, name, entity_id, icon_url, sort_order, enabled, default_volume, max_volume,
+            allow_takeover, announce
+        FROM rooms
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(result)
+}
+
+pub async fn list_by_ids(db: &PgPool, room_ids: &[String]) -> anyhow::Result<Vec<Room>> {
+    let ids = room_ids
+        .iter()
+        .map(|id| sqlx::types::Uuid::parse_str(id))
+        .collect::<Result<Vec<_>, _>>()?;
+    let result = sqlx::query_as!(
+        Room,
+        r#"
+        SELECT 
+            id, name, entity_id, icon_url, sort_order, enabled, default_volume, max_volume,
+            allow_takeover, announce
+        FROM rooms
+        WHERE id = ANY($1)
+        ORDER BY sort_order, name
+        "#,
+        &ids
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(result)
+}
+
+/// the room the last episode of a series was played in
+pub async fn last_used(db: &PgPool, category_id: &str) -> anyhow::Result<Option<Room>> {
+    let id = sqlx::types::Uuid::parse_str(category_id)?;
+    let result = sqlx::query_as!(
+        Room,
+        r#"
+        SELECT 
+            r.id, r.name, r.entity_id, r.icon_url, r.sort_order, r.enabled, r.default_volume,
+            r.max_volume, r.allow_takeover, r.announce
+        FROM rooms AS r
+        JOIN categories AS c ON c.last_room_id = r.id
+        WHERE c.id = $1 AND r.enabled = TRUE
+        "#,
+        id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(result)
+}
+
+async fn delete(db: &PgPool, room_id: &str) -> anyhow::Result<()> {
+    let id = sqlx::types::Uuid::parse_str(room_id)?;
+    sqlx::query!(
+        r#"
+        DELETE FROM rooms 
+        WHERE id = $1
+        "#,
+        id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+async fn update(db: &PgPool, room: &Room) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE rooms
+        SET
+            name = $2, entity_id = $3, icon_url = $4, sort_order = $5, enabled = $6,
+            default_volume = $7, max_volume = $8, allow_takeover = $9, announce = $10
+        WHERE id = $1
+        "#,
+        room.id,
+        room.name,
+        room.entity_id,
+        room.icon_url,
+        room.sort_order,
+        room.enabled,
+        room.default_volume,
+        room.max_volume,
+        room.allow_takeover,
+        room.announce
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+async fn create(
+    db: &PgPool,
+    name: &str,
+    entity_id: &str,
+    icon_url: &str,
+    sort_order: i32,
+    default_volume: f32,
+    max_volume: f32,
+) -> anyhow::Result<Uuid> {
+    let rec = sqlx::query!(
+        r#"
+        INSERT INTO rooms (name, entity_id, icon_url, sort_order, default_volume, max_volume)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id
+        "#,
+        name,
+        entity_id,
+        icon_url,
+        sort_order,
+        default_volume,
+        max_volume
+    )
+    .fetch_one(db)
+    .await?;
+    Ok(rec.id)
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct RoomSchedule {
+    pub id: sqlx::types::Uuid,
+    pub starts_at: String,
+    pub ends_at: String,
+}
+
+pub async fn list_schedules(db: &PgPool, room_id: &str) -> anyhow::Result<Vec<RoomSchedule>> {
+    let id = sqlx::types::Uuid::parse_str(room_id)?;
+    let result = sqlx::query_as!(
+        RoomSchedule,
+        r#"
+        SELECT
+            id, to_char(starts_at, 'HH24:MI') AS "starts_at!", to_char(ends_at, 'HH24:MI') AS "ends_at!"
+        FROM room_schedules
+        WHERE room_id = $1
+        ORDER BY starts_at
+        "#,
+        id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(result)
+}
+
+async fn create_schedule(
+    db: &PgPool,
+    room_id: &str,
+    starts_at: &str,
+    ends_at: &str,
+) -> anyhow::Result<Uuid> {
+    let id = sqlx::types::Uuid::parse_str(room_id)?;
+    let rec = sqlx::query!(
+        r#"
+        INSERT INTO room_schedules (room_id, starts_at, ends_at)
+        VALUES ($1, ($2::text)::time, ($3::text)::time)
+        RETURNING id
+        "#,
+        id,
+        starts_at,
+        ends_at
+    )
+    .fetch_one(db)
+    .await?;
+    Ok(rec.id)
+}
+
+async fn delete_schedule(db: &PgPool, room_id: &str, schedule_id: &str) -> anyhow::Result<()> {
+    let room_id = sqlx::types::Uuid::parse_str(room_id)?;
+    let id = sqlx::types::Uuid::parse_str(schedule_id)?;
+    sqlx::query!(
+        r#"
+        DELETE FROM room_schedules
+        WHERE id = $1 AND room_id = $2
+        "#,
+        id,
+        room_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// schedules are compared in postgres, so the timezone has to be one it knows
+pub async fn check_timezone(db: &PgPool, timezone: &str) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        SELECT NOW() AT TIME ZONE $1 AS local_time
+        "#,
+        timezone
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(())
+}
+
+/// a room without schedules is always allowed to play, windows may span midnight
+pub async fn is_playback_allowed(
+    db: &PgPool,
+    room_id: &sqlx::types::Uuid,
+    timezone: &str,
+) -> anyhow::Result<bool> {
+    let rec = sqlx::query!(
+        r#"
+        WITH now AS (
+            SELECT (NOW() AT TIME ZONE $2)::time AS local_time
+        )
+        SELECT
+            NOT EXISTS (SELECT 1 FROM room_schedules WHERE room_id = $1)
+            OR EXISTS (
+                SELECT 1
+                FROM room_schedules AS s, now
+                WHERE s.room_id = $1
+                    AND CASE
+                        WHEN s.starts_at <= s.ends_at
+                            THEN now.local_time >= s.starts_at AND now.local_time < s.ends_at
+                        ELSE now.local_time >= s.starts_at OR now.local_time < s.ends_at
+                    END
+            ) AS "allowed!"
+        "#,
+        room_id,
+        timezone
+    )
+    .fetch_one(db)
+    .await?;
+    Ok(rec.allowed)
+}
+
+pub async fn record_play(db: &PgPool, room_id: &Uuid, entry_id: &Uuid) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO room_plays (room_id, entry_id)
+        VALUES ($1, $2)
+        ON CONFLICT (room_id) DO UPDATE
+        SET entry_id = EXCLUDED.entry_id, started_at = NOW()
+        "#,
+        room_id,
+        entry_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// the entry this app started in the room within the last `minutes`
+pub async fn recent_play(
+    db: &PgPool,
+    room_id: &Uuid,
+    minutes: i32,
+) -> anyhow::Result<Option<Uuid>> {
+    let result = sqlx::query_scalar!(
+        r#"
+        SELECT entry_id
+        FROM room_plays
+        WHERE room_id = $1 AND started_at > NOW() - make_interval(mins => $2)
+        "#,
+        room_id,
+        minutes
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(result)
+}
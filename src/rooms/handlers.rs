@@ -0,0 +1,192 @@
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    http::HeaderMap,
+    response::{Html, IntoResponse},
+};
+use axum_extra::extract::Form;
+use serde::Deserialize;
+
+use crate::{errors, states};
+
+use super::{Room, RoomSchedule};
+
+#[derive(Template)]
+#[template(path = "admin_rooms.html")]
+struct ListTemplate {
+    rooms: Vec<Room>,
+}
+
+pub async fn admin_list(
+    State(state): State<states::AppState>,
+) -> Result<impl IntoResponse, errors::AppError> {
+    let rooms = super::list_all(&state.db).await?;
+    Ok(Html(ListTemplate { rooms }.render()?))
+}
+
+#[derive(Template)]
+#[template(path = "admin_rooms_create.html")]
+struct CreateTemplate {}
+
+pub async fn admin_new() -> Result<impl IntoResponse, errors::AppError> {
+    Ok(Html(CreateTemplate {}.render()?))
+}
+
+#[derive(Deserialize)]
+pub struct RoomCreateForm {
+    name: String,
+    entity_id: String,
+    icon_url: String,
+    sort_order: i32,
+    default_volume: f32,
+    max_volume: f32,
+}
+
+/// volumes are fractions of the speaker maximum and the default has to stay below the room maximum
+fn check_volumes(default_volume: f32, max_volume: f32) -> anyhow::Result<()> {
+    let valid = 0.0 <= default_volume && default_volume <= max_volume && max_volume <= 1.0;
+    if !valid {
+        return Err(anyhow::anyhow!(
+            "volumes must satisfy 0 <= default ({}) <= maximum ({}) <= 1",
+            default_volume,
+            max_volume
+        ));
+    }
+    Ok(())
+}
+
+pub async fn admin_create(
+    State(state): State<states::AppState>,
+    Form(room_form): Form<RoomCreateForm>,
+) -> Result<impl IntoResponse, errors::AppError> {
+    check_volumes(room_form.default_volume, room_form.max_volume)?;
+    let id = super::create(
+        &state.db,
+        &room_form.name,
+        &room_form.entity_id,
+        &room_form.icon_url,
+        room_form.sort_order,
+        room_form.default_volume,
+        room_form.max_volume,
+    )
+    .await?;
+
+    let mut headers = HeaderMap::new();
+    let path = format!("/admin/rooms/{}", id);
+    headers.insert("HX-Redirect", path.parse()?);
+    Ok(headers)
+}
+
+#[derive(Template)]
+#[template(path = "admin_rooms_edit.html")]
+struct EditTemplate {
+    room: Room,
+    schedules: Vec<RoomSchedule>,
+}
+
+pub async fn admin_get_room(
+    Path(room_id): Path<String>,
+    State(state): State<states::AppState>,
+) -> Result<impl IntoResponse, errors::AppError> {
+    let room = super::get(&state.db, &room_id).await?;
+    let schedules = super::list_schedules(&state.db, &room_id).await?;
+    Ok(Html(EditTemplate { room, schedules }.render()?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RoomEditForm {
+    id: String,
+    name: String,
+    entity_id: String,
+    icon_url: String,
+    sort_order: i32,
+    #[serde(default)]
+    enabled: bool,
+    default_volume: f32,
+    max_volume: f32,
+    #[serde(default)]
+    allow_takeover: bool,
+    #[serde(default)]
+    announce: bool,
+}
+
+impl TryInto<Room> for RoomEditForm {
+    type Error = anyhow::Error;
+
+    fn try_into(self) -> anyhow::Result<Room, Self::Error> {
+        let id = sqlx::types::Uuid::parse_str(&self.id)?;
+        check_volumes(self.default_volume, self.max_volume)?;
+        Ok(Room {
+            id,
+            name: self.name,
+            entity_id: self.entity_id,
+            icon_url: self.icon_url,
+            sort_order: self.sort_order,
+            enabled: self.enabled,
+            default_volume: self.default_volume,
+            max_volume: self.max_volume,
+            allow_takeover: self.allow_takeover,
+            announce: self.announce,
+        })
+    }
+}
+
+pub async fn admin_update(
+    State(state): State<states::AppState>,
+    Form(room_form): Form<RoomEditForm>,
+) -> Result<impl IntoResponse, errors::AppError> {
+    let room = room_form.try_into()?;
+    super::update(&state.db, &room).await?;
+    let mut headers = HeaderMap::new();
+    let path = format!("/admin/rooms/{}", room.id);
+    headers.insert("HX-Redirect", path.parse()?);
+    Ok(headers)
+}
+
+pub async fn admin_delete(
+    Path(room_id): Path<String>,
+    State(state): State<states::AppState>,
+) -> Result<impl IntoResponse, errors::AppError> {
+    super::delete(&state.db, &room_id).await?;
+    let mut headers = HeaderMap::new();
+    headers.insert("HX-Redirect", "/admin/rooms".parse().unwrap());
+    Ok(headers)
+}
+
+#[derive(Template)]
+#[template(path = "admin_partial_schedule_list.html")]
+struct ScheduleListTemplate {
+    room_id: String,
+    schedules: Vec<RoomSchedule>,
+}
+
+#[derive(Deserialize)]
+pub struct ScheduleCreateForm {
+    starts_at: String,
+    ends_at: String,
+}
+
+pub async fn admin_create_schedule(
+    Path(room_id): Path<String>,
+    State(state): State<states::AppState>,
+    Form(schedule_form): Form<ScheduleCreateForm>,
+) -> Result<impl IntoResponse, errors::AppError> {
+    super::create_schedule(
+        &state.db,
+        &room_id,
+        &schedule_form.starts_at,
+        &schedule_form.ends_at,
+    )
+    .await?;
+    let schedules = super::list_schedules(&state.db, &room_id).await?;
+    Ok(Html(ScheduleListTemplate { room_id, schedules }.render()?))
+}
+
+pub async fn admin_delete_schedule(
+    Path((room_id, schedule_id)): Path<(String, String)>,
+    State(state): State<states::AppState>,
+) -> Result<impl IntoResponse, errors::AppError> {
+    super::delete_schedule(&state.db, &room_id, &schedule_id).await?;
+    let schedules = super::list_schedules(&state.db, &room_id).await?;
+    Ok(Html(ScheduleListTemplate { room_id, schedules }.render()?))
+}
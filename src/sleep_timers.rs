@@ -0,0 +1,211 @@
+use std::time::Duration;
+
+use sqlx::PgPool;
+use tracing::{error, info, warn};
+
+use crate::{playback::Backend, rooms::Room, states};
+
+pub mod handlers;
+
+pub const DURATIONS: [i32; 3] = [15, 30, 45];
+const FADE_STEPS: u8 = 10;
+/// a timer that keeps failing is given up once it is this late
+const EXPIRY_MINUTES: i32 = 5;
+
+#[derive(Debug)]
+pub struct SleepTimer {
+    pub room_id: sqlx::types::Uuid,
+    pub minutes_left: i64,
+}
+
+pub async fn list_all(db: &PgPool) -> anyhow::Result<Vec<SleepTimer>> {
+    let result = sqlx::query_as!(
+        SleepTimer,
+        r#"
+        SELECT
+            room_id, GREATEST(CEIL(EXTRACT(EPOCH FROM fires_at - NOW()) / 60), 0)::bigint AS "minutes_left!"
+        FROM sleep_timers
+        "#,
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(result)
+}
+
+/// only the durations offered on the entry page can be set
+pub async fn set(db: &PgPool, room_id: &sqlx::types::Uuid, minutes: i32) -> anyhow::Result<()> {
+    if !DURATIONS.contains(&minutes) {
+        return Err(anyhow::anyhow!(
+            "unsupported sleep timer of {} minutes",
+            minutes
+        ));
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO sleep_timers (room_id, fires_at)
+        VALUES ($1, NOW() + make_interval(mins => $2))
+        ON CONFLICT (room_id) DO UPDATE
+        SET fires_at = EXCLUDED.fires_at, fired_at = NULL
+        "#,
+        room_id,
+        minutes
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete(db: &PgPool, room_id: &sqlx::types::Uuid) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        DELETE FROM sleep_timers
+        WHERE room_id = $1
+        "#,
+        room_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// marks the due timers as fired in the same statement so that every fade starts only once
+async fn take_due(db: &PgPool) -> anyhow::Result<Vec<Room>> {
+    let result = sqlx::query_as!(
+        Room,
+        r#"
+        WITH due AS (
+            UPDATE sleep_timers
+            SET fired_at = NOW()
+            WHERE fires_at <= NOW() AND fired_at IS NULL
+            RETURNING room_id
+        )
+        SELECT
+            r.id, r.name, r.entity_id, r.icon_url, r.sort_order, r.enabled, r.default_volume, r.max_volume,
+            r.allow_takeover, r.announce
+        FROM rooms AS r
+        JOIN due ON due.room_id = r.id
+        "#,
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(result)
+}
+
+/// timers still waiting long after they were due failed every retry
+async fn expire(db: &PgPool) -> anyhow::Result<u64> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM sleep_timers
+        WHERE fired_at IS NULL AND fires_at < NOW() - make_interval(mins => $1)
+        "#,
+        EXPIRY_MINUTES
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// the timer stays until playback is stopped, a new timer set during the fade is kept
+async fn finish(db: &PgPool, room_id: &sqlx::types::Uuid) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        DELETE FROM sleep_timers
+        WHERE room_id = $1 AND fired_at IS NOT NULL
+        "#,
+        room_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// fades interrupted by a restart or a failure start over with the next check
+async fn release(db: &PgPool, room_id: Option<&sqlx::types::Uuid>) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE sleep_timers
+        SET fired_at = NULL
+        WHERE $1::uuid IS NULL OR room_id = $1
+        "#,
+        room_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// spotify cannot pause a device twice, so a paused or idle player counts as stopped
+async fn is_stopped(player: &dyn Backend, room: &Room) -> anyhow::Result<bool> {
+    Ok(player
+        .player(room)
+        .await?
+        .is_none_or(|p| !p.is_on() || p.state == "paused"))
+}
+
+async fn fade_out(player: &dyn Backend, room: &Room) -> anyhow::Result<()> {
+    if is_stopped(player, room).await? {
+        info!("sleep timer found nothing playing in {}", room.name);
+        return Ok(());
+    }
+    let volume = player.volume(room).await?;
+
+    for step in 1..=FADE_STEPS {
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        let factor = f32::from(FADE_STEPS - step) / f32::from(FADE_STEPS);
+        player.volume_set(room, volume * factor).await?;
+    }
+    if let Err(err) = player.stop(room).await
+        && !is_stopped(player, room).await?
+    {
+        return Err(err.into());
+    }
+    player.unjoin(room).await?;
+    info!("sleep timer stopped playback in {}", room.name);
+    Ok(())
+}
+
+pub async fn run(state: states::AppState) {
+    info!("watching sleep timers");
+    if let Err(err) = release(&state.db, None).await {
+        error!("could not release interrupted sleep timers: {}", err);
+    }
+    let mut interval = tokio::time::interval(Duration::from_secs(15));
+    loop {
+        interval.tick().await;
+        match expire(&state.db).await {
+            Ok(0) => {}
+            Ok(expired) => warn!("gave up on {} sleep timers that kept failing", expired),
+            Err(err) => error!("could not expire sleep timers: {}", err),
+        }
+        let rooms = match take_due(&state.db).await {
+            Ok(rooms) => rooms,
+            Err(err) => {
+                error!("could not load due sleep timers: {}", err);
+                continue;
+            }
+        };
+
+        for room in rooms {
+            let state = state.clone();
+            tokio::spawn(async move {
+                let done = match fade_out(state.player.as_ref(), &room).await {
+                    Ok(()) => finish(&state.db, &room.id).await,
+                    Err(err) => {
+                        error!("sleep timer failed in {}: {}", room.name, err);
+                        release(&state.db, Some(&room.id)).await
+                    }
+                };
+                if let Err(err) = done {
+                    error!("could not update sleep timer in {}: {}", room.name, err);
+                }
+            });
+        }
+    }
+}
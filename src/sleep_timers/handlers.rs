@@ -0,0 +1,33 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse};
+use axum_extra::extract::Form;
+use serde::Deserialize;
+use tracing::info;
+
+use crate::{errors, rooms, states};
+
+#[derive(Deserialize, Debug)]
+pub struct SleepTimerForm {
+    #[serde(default)]
+    room: Vec<String>,
+    minutes: i32,
+}
+
+pub async fn set(
+    State(state): State<states::AppState>,
+    Form(sleep_timer_form): Form<SleepTimerForm>,
+) -> Result<impl IntoResponse, errors::AppError> {
+    for room in rooms::list_by_ids(&state.db, &sleep_timer_form.room).await? {
+        if sleep_timer_form.minutes > 0 {
+            super::set(&state.db, &room.id, sleep_timer_form.minutes).await?;
+            info!(
+                "sleep timer in {} set to {} minutes",
+                room.name, sleep_timer_form.minutes
+            );
+        } else {
+            super::delete(&state.db, &room.id).await?;
+            info!("sleep timer in {} cancelled", room.name);
+        }
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
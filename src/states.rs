@@ -1,11 +1,17 @@
-use rspotify::ClientCredsSpotify;
+use rspotify::{AuthCodeSpotify, ClientCredsSpotify};
 use sqlx::PgPool;
 
-use crate::ha::Client;
+use std::sync::Arc;
+
+use crate::playback::{Availability, Backend};
 
 #[derive(Debug, Clone)]
 pub struct AppState {
     pub db: PgPool,
     pub spotify: ClientCredsSpotify,
-    pub ha_client: Client,
+    pub spotify_auth: Option<AuthCodeSpotify>,
+    pub player: Arc<dyn Backend>,
+    pub availability: Availability,
+    pub timezone: String,
+    pub hide_explicit: bool,
 }